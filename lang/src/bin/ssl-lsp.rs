@@ -0,0 +1,1077 @@
+//! A language server for `.ssl` files, speaking LSP on stdin and stdout.
+//!
+//! Editors send the text of every open sheet as it changes. The server parses it
+//! again after each change and publishes what `ssl check` would report for it.
+//! Requests about a sheet, like go-to-definition, are answered by [`lang::ide`].
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::ops;
+use std::path::{Path, PathBuf};
+
+use cstree::text::{TextRange, TextSize};
+use lang::diagnostic::{self, Code, Severity};
+use lang::ide::{self, Modifiers, TokenClass};
+use lang::line_index::{LineIndex, WideEncoding, WideLineCol};
+use lang::lint::{self, Config};
+use lang::sema::Sheet;
+use lang::{Parse, TextEdit};
+use lsp_server::{
+    Connection, ErrorCode, Message, Notification, ProtocolError, Request, RequestId, Response,
+};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, GotoDefinition, HoverRequest, References, RegisterCapability, Rename,
+    Request as LspRequest, SemanticTokensFullRequest,
+};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, DiagnosticRelatedInformation, DiagnosticSeverity, DiagnosticTag,
+    DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
+    DidChangeWatchedFilesRegistrationOptions, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, FileSystemWatcher, GlobPattern, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability,
+    InitializeParams, Location, MarkupContent, MarkupKind, NumberOrString, OneOf, Position,
+    PublishDiagnosticsParams, Range, ReferenceParams, Registration, RegistrationParams,
+    RenameParams, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens,
+    SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensResult, ServerCapabilities, TextDocumentPositionParams,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url, WorkspaceEdit,
+};
+use serde_json::json;
+
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["$".to_owned(), "\"".to_owned()]),
+            ..CompletionOptions::default()
+        }),
+        semantic_tokens_provider: Some(
+            SemanticTokensOptions {
+                legend: legend(),
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                ..SemanticTokensOptions::default()
+            }
+            .into(),
+        ),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// The semantic token types, indexed like [`TokenClass::ALL`], and modifiers, in
+/// the order of the bits of [`Modifiers`].
+fn legend() -> SemanticTokensLegend {
+    let token_types = TokenClass::ALL
+        .into_iter()
+        .map(|class| match class {
+            TokenClass::Cell => SemanticTokenType::VARIABLE,
+            TokenClass::Range => SemanticTokenType::new("range"),
+            TokenClass::Alias => SemanticTokenType::MACRO,
+            TokenClass::Keyword => SemanticTokenType::KEYWORD,
+            TokenClass::Number => SemanticTokenType::NUMBER,
+            TokenClass::String => SemanticTokenType::STRING,
+            TokenClass::EnumMember => SemanticTokenType::ENUM_MEMBER,
+            TokenClass::Comment => SemanticTokenType::COMMENT,
+            TokenClass::Directive => SemanticTokenType::DECORATOR,
+        })
+        .collect();
+    let token_modifiers = Modifiers::ALL
+        .into_iter()
+        .filter_map(|modifier| modifier.name().map(SemanticTokenModifier::new))
+        .collect();
+    SemanticTokensLegend {
+        token_types,
+        token_modifiers,
+    }
+}
+
+/// Answer the `initialize` handshake, then handle messages until the client shuts
+/// the server down.
+fn serve(connection: &Connection) -> Result<(), ProtocolError> {
+    let (id, params) = connection.initialize_start()?;
+    connection.initialize_finish(
+        id,
+        json!({
+            "capabilities": capabilities(),
+            "serverInfo": {
+                "name": "ssl-lsp",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        }),
+    )?;
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+        configs: HashMap::new(),
+    };
+    // Clients that can't watch files for us keep the lint levels the server
+    // started with, which is better than reading `ssl.toml` on every change.
+    let watch = serde_json::from_value::<InitializeParams>(params)
+        .ok()
+        .and_then(|params| params.capabilities.workspace?.did_change_watched_files)
+        .and_then(|capability| capability.dynamic_registration)
+        .unwrap_or(false);
+    if watch {
+        server.watch_config_files();
+    }
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                server.request(request);
+            }
+            Message::Notification(notification) => server.notify(notification),
+            // we never send requests, so there is nothing to respond to
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// A sheet that is open in the editor. Its text is the one in the editor, which
+/// can differ from the one on disk.
+struct Document {
+    version: i32,
+    index: LineIndex,
+    parse: Parse,
+    sheet: Sheet,
+}
+
+impl Document {
+    fn new(text: &str, version: i32) -> Self {
+        Document::with_parse(version, lang::parse(text))
+    }
+
+    /// A document whose text was already parsed.
+    fn with_parse(version: i32, parse: Parse) -> Self {
+        let index = LineIndex::new(parse.source());
+        let sheet = lang::analyze(&parse);
+        Document {
+            version,
+            index,
+            parse,
+            sheet,
+        }
+    }
+
+    /// Everything `ssl check` would report: the syntax errors, or if there are
+    /// none the semantic errors and the lints that aren't allowed.
+    fn check(&self, config: &Config) -> Vec<diagnostic::Diagnostic> {
+        if !self.parse.errors.is_empty() {
+            return self.parse.errors.clone();
+        }
+        let mut diagnostics: Vec<_> = self
+            .sheet
+            .issues
+            .iter()
+            .filter(|issue| issue.is_error())
+            .map(|issue| issue.diagnostic(self.parse.source()))
+            .collect();
+        let source = self.parse.source();
+        diagnostics.extend(lint::lint(&self.parse, &self.sheet, source, config));
+        diagnostics
+    }
+
+    fn location(&self, uri: &Url, span: TextRange) -> Location {
+        Location::new(uri.clone(), range(&self.index, span))
+    }
+}
+
+struct Server<'c> {
+    connection: &'c Connection,
+    documents: HashMap<Url, Document>,
+    /// The lint levels of each directory with an open document, until a project
+    /// file changes.
+    configs: HashMap<PathBuf, Config>,
+}
+
+impl Server<'_> {
+    fn request(&self, request: Request) {
+        let method = request.method.clone();
+        let response = match method.as_str() {
+            GotoDefinition::METHOD => self.handle::<GotoDefinition>(request, Self::definition),
+            References::METHOD => self.handle::<References>(request, Self::references),
+            Rename::METHOD => self.handle::<Rename>(request, Self::rename),
+            Completion::METHOD => self.handle::<Completion>(request, Self::completion),
+            HoverRequest::METHOD => self.handle::<HoverRequest>(request, Self::hover),
+            SemanticTokensFullRequest::METHOD => {
+                self.handle::<SemanticTokensFullRequest>(request, Self::semantic_tokens)
+            }
+            _ => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{method}`"),
+            ),
+        };
+        self.send(response.into());
+    }
+
+    /// Answer a request with `handler`, or with an error if the request is invalid
+    /// or the handler fails.
+    fn handle<R: LspRequest>(
+        &self,
+        request: Request,
+        handler: impl FnOnce(&Self, R::Params) -> Result<R::Result, String>,
+    ) -> Response {
+        let id = request.id.clone();
+        match request.extract(R::METHOD) {
+            Ok((id, params)) => match handler(self, params) {
+                Ok(result) => Response::new_ok(id, result),
+                Err(message) => Response::new_err(id, ErrorCode::RequestFailed as i32, message),
+            },
+            Err(err) => Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string()),
+        }
+    }
+
+    fn document(&self, uri: &Url) -> Result<&Document, String> {
+        self.documents
+            .get(uri)
+            .ok_or_else(|| format!("{uri} is not open"))
+    }
+
+    /// The document a request is about, and the offset of its position in it.
+    fn document_at(
+        &self,
+        position: &TextDocumentPositionParams,
+    ) -> Result<(&Document, TextSize), String> {
+        let document = self.document(&position.text_document.uri)?;
+        Ok((document, offset(&document.index, position.position)))
+    }
+
+    fn definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>, String> {
+        let position = params.text_document_position_params;
+        let (document, offset) = self.document_at(&position)?;
+        let span = ide::definition(&document.parse, &document.sheet, offset);
+        let uri = &position.text_document.uri;
+        Ok(span.map(|span| GotoDefinitionResponse::Scalar(document.location(uri, span))))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>, String> {
+        let position = params.text_document_position;
+        let (document, offset) = self.document_at(&position)?;
+        let mut spans = ide::references(&document.parse, &document.sheet, offset);
+        if !params.context.include_declaration {
+            let definition = ide::definition(&document.parse, &document.sheet, offset);
+            spans.retain(|&span| Some(span) != definition);
+        }
+        let uri = &position.text_document.uri;
+        let locations = spans
+            .into_iter()
+            .map(|span| document.location(uri, span))
+            .collect();
+        Ok(Some(locations))
+    }
+
+    fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let position = params.text_document_position;
+        let (document, offset) = self.document_at(&position)?;
+        let edits = ide::rename(&document.parse, &document.sheet, offset, &params.new_name)
+            .map_err(|err| err.to_string())?;
+        let edits = edits
+            .into_iter()
+            .map(|edit| {
+                lsp_types::TextEdit::new(range(&document.index, edit.span), edit.replacement)
+            })
+            .collect();
+        let changes = HashMap::from([(position.text_document.uri.clone(), edits)]);
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>, String> {
+        let (document, offset) = self.document_at(&params.text_document_position)?;
+        let items = ide::completions(&document.parse, &document.sheet, offset)
+            .into_iter()
+            .map(|completion| {
+                let kind = match completion.kind {
+                    ide::CompletionKind::Alias => CompletionItemKind::VARIABLE,
+                    ide::CompletionKind::Range => CompletionItemKind::REFERENCE,
+                    ide::CompletionKind::EnumMember => CompletionItemKind::ENUM_MEMBER,
+                };
+                let edit = lsp_types::TextEdit::new(
+                    range(&document.index, completion.span),
+                    completion.label.clone(),
+                );
+                CompletionItem {
+                    label: completion.label,
+                    kind: Some(kind),
+                    detail: completion.detail,
+                    text_edit: Some(CompletionTextEdit::Edit(edit)),
+                    ..CompletionItem::default()
+                }
+            })
+            .collect();
+        Ok(Some(CompletionResponse::Array(items)))
+    }
+
+    fn hover(&self, params: HoverParams) -> Result<Option<Hover>, String> {
+        let (document, offset) = self.document_at(&params.text_document_position_params)?;
+        let hover = ide::hover(&document.parse, &document.sheet, offset);
+        Ok(hover.map(|hover| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: hover.markdown(),
+            }),
+            range: Some(range(&document.index, hover.span)),
+        }))
+    }
+
+    fn semantic_tokens(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>, String> {
+        let document = self.document(&params.text_document.uri)?;
+        // Each token is relative to the start of the previous one.
+        let mut previous = Position::new(0, 0);
+        let data = ide::highlight(&document.parse, &document.sheet)
+            .into_iter()
+            .flat_map(|highlight| {
+                // a token can't span lines, unlike a string
+                lines(document.parse.source(), highlight.span).map(move |span| (span, highlight))
+            })
+            .map(|(span, highlight)| {
+                let Range { start, end } = range(&document.index, span);
+                let delta_start = if start.line == previous.line {
+                    start.character - previous.character
+                } else {
+                    start.character
+                };
+                let token = SemanticToken {
+                    delta_line: start.line - previous.line,
+                    delta_start,
+                    length: end.character - start.character,
+                    token_type: highlight.class as u32,
+                    token_modifiers_bitset: highlight.modifiers.bits(),
+                };
+                previous = start;
+                token
+            })
+            .collect();
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data,
+        })))
+    }
+
+    fn notify(&mut self, notification: Notification) {
+        let method = notification.method.clone();
+        let result = match method.as_str() {
+            DidOpenTextDocument::METHOD => notification
+                .extract(DidOpenTextDocument::METHOD)
+                .map(|params| self.open(params)),
+            DidChangeTextDocument::METHOD => notification
+                .extract(DidChangeTextDocument::METHOD)
+                .map(|params| self.change(params)),
+            DidCloseTextDocument::METHOD => notification
+                .extract(DidCloseTextDocument::METHOD)
+                .map(|params| self.close(params)),
+            DidChangeWatchedFiles::METHOD => notification
+                .extract(DidChangeWatchedFiles::METHOD)
+                .map(|params| self.config_changed(params)),
+            // notifications can be ignored, unlike requests
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!("error: invalid `{method}` notification: {err}");
+        }
+    }
+
+    fn open(&mut self, params: DidOpenTextDocumentParams) {
+        let document = params.text_document;
+        self.documents.insert(
+            document.uri.clone(),
+            Document::new(&document.text, document.version),
+        );
+        self.publish(&document.uri);
+    }
+
+    fn change(&mut self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        let Some(document) = self.documents.get_mut(&uri) else {
+            eprintln!("error: {uri} was changed without being opened");
+            return;
+        };
+        let mut parse = std::mem::replace(&mut document.parse, lang::parse(""));
+        for (i, change) in params.content_changes.into_iter().enumerate() {
+            match change.range {
+                Some(range) => {
+                    // Every change is relative to the text after the previous one,
+                    // so only the first one can use the index of the document.
+                    let fresh;
+                    let index = if i == 0 {
+                        &document.index
+                    } else {
+                        fresh = LineIndex::new(parse.source());
+                        &fresh
+                    };
+                    let edit = TextEdit {
+                        span: text_range(index, range),
+                        replacement: change.text,
+                    };
+                    // positions past the end of a line or of the text are moved
+                    // back into it, so this shouldn't happen
+                    if let Err(err) = parse.reparse(&edit) {
+                        eprintln!("error: invalid change to {uri}: {err}");
+                    }
+                }
+                None => parse = lang::parse(&change.text),
+            }
+        }
+        *document = Document::with_parse(params.text_document.version, parse);
+        self.publish(&uri);
+    }
+
+    fn close(&mut self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.documents.remove(&uri);
+        // the diagnostics of closed files are up to the client, so clear ours
+        let params = PublishDiagnosticsParams::new(uri, Vec::new(), None);
+        self.send(Notification::new(PublishDiagnostics::METHOD.to_owned(), params).into());
+    }
+
+    /// Ask the client to tell us when a project file changes, since that changes
+    /// the lint levels of every document under it.
+    fn watch_config_files(&self) {
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String(format!("**/{}", lint::CONFIG_FILE)),
+                kind: None,
+            }],
+        };
+        let params = RegistrationParams {
+            registrations: vec![Registration {
+                id: DidChangeWatchedFiles::METHOD.to_owned(),
+                method: DidChangeWatchedFiles::METHOD.to_owned(),
+                register_options: serde_json::to_value(options).ok(),
+            }],
+        };
+        // The response only tells whether the client agreed, which changes nothing.
+        let id = RequestId::from(DidChangeWatchedFiles::METHOD.to_owned());
+        self.send(Request::new(id, RegisterCapability::METHOD.to_owned(), params).into());
+    }
+
+    /// A project file was created, changed or deleted: forget every level that was
+    /// read, since a new file can apply to any directory below it, and check the
+    /// open documents again.
+    fn config_changed(&mut self, _params: DidChangeWatchedFilesParams) {
+        self.configs.clear();
+        let uris: Vec<_> = self.documents.keys().cloned().collect();
+        for uri in &uris {
+            self.publish(uri);
+        }
+    }
+
+    /// Check a document again and replace its diagnostics.
+    fn publish(&mut self, uri: &Url) {
+        let config = config(&mut self.configs, uri);
+        let document = &self.documents[uri];
+        let diagnostics = document
+            .check(config)
+            .iter()
+            .map(|diagnostic| to_lsp(uri, &document.index, diagnostic))
+            .collect();
+        let params =
+            PublishDiagnosticsParams::new(uri.clone(), diagnostics, Some(document.version));
+        self.send(Notification::new(PublishDiagnostics::METHOD.to_owned(), params).into());
+    }
+
+    fn send(&self, message: Message) {
+        // This only fails once the client is gone, and then the main loop stops
+        // anyway because there are no more messages.
+        let _ = self.connection.sender.send(message);
+    }
+}
+
+/// The lint levels for a document, from the closest project file above it, read
+/// once for each directory. Documents that aren't files, and files with an invalid
+/// project file, get the default levels.
+fn config<'a>(configs: &'a mut HashMap<PathBuf, Config>, uri: &Url) -> &'a Config {
+    let path = uri.to_file_path().ok();
+    let dir = path.as_deref().and_then(Path::parent).map(Path::to_owned);
+    configs.entry(dir.unwrap_or_default()).or_insert_with(|| {
+        let Some(file) = path.and_then(|path| lint::config_file(&path)) else {
+            return Config::default();
+        };
+        let config = std::fs::read_to_string(&file)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Config::from_toml(&text));
+        config.unwrap_or_else(|err| {
+            eprintln!("error: invalid {}: {err}", file.display());
+            Config::default()
+        })
+    })
+}
+
+fn to_lsp(
+    uri: &Url,
+    index: &LineIndex,
+    diagnostic: &diagnostic::Diagnostic,
+) -> lsp_types::Diagnostic {
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message.push_str("\nnote: ");
+        message.push_str(note);
+    }
+    let related: Vec<_> = diagnostic
+        .secondary
+        .iter()
+        .map(|label| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), range(index, label.span)),
+            message: label.message.clone(),
+        })
+        .collect();
+    lsp_types::Diagnostic {
+        range: range(index, diagnostic.primary.span),
+        severity: Some(match diagnostic.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+        }),
+        code: Some(NumberOrString::String(diagnostic.code.id().to_owned())),
+        source: Some("ssl".to_owned()),
+        message,
+        related_information: (!related.is_empty()).then_some(related),
+        // editors grey out unnecessary code
+        tags: (diagnostic.code == Code::UnusedAlias).then(|| vec![DiagnosticTag::UNNECESSARY]),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+fn position(index: &LineIndex, offset: TextSize) -> Position {
+    let wide = index.to_wide(WideEncoding::Utf16, index.line_col(offset));
+    Position::new(wide.line, wide.col)
+}
+
+fn range(index: &LineIndex, span: TextRange) -> Range {
+    Range::new(position(index, span.start()), position(index, span.end()))
+}
+
+/// The non-empty parts of `span` on each line, without the line breaks.
+fn lines(text: &str, span: TextRange) -> impl Iterator<Item = TextRange> + '_ {
+    let mut offset = span.start();
+    text[ops::Range::<usize>::from(span)]
+        .split('\n')
+        .map(move |line| {
+            let part = TextRange::at(offset, TextSize::of(line.trim_end_matches('\r')));
+            offset += TextSize::of(line) + TextSize::of('\n');
+            part
+        })
+        .filter(|part| !part.is_empty())
+}
+
+/// The offset of a position. Like LSP says, a position past the end of its line
+/// means the end of the line; one past the last line means the end of the text.
+fn offset(index: &LineIndex, position: Position) -> TextSize {
+    let Some(line) = index.line_range(position.line) else {
+        return index.len();
+    };
+    let line_col = index.to_utf8(
+        WideEncoding::Utf16,
+        WideLineCol {
+            line: position.line,
+            col: position.character,
+        },
+    );
+    index.offset(line_col).unwrap_or(line.end())
+}
+
+fn text_range(index: &LineIndex, range: Range) -> TextRange {
+    let start = offset(index, range.start);
+    TextRange::new(start, offset(index, range.end).max(start))
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::{
+        FileChangeType, FileEvent, InitializedParams, ReferenceContext,
+        TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+        VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+
+    /// A fake editor, talking to a server on another thread.
+    struct Client {
+        connection: Connection,
+        server: JoinHandle<Result<(), ProtocolError>>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = std::thread::spawn(move || serve(&server));
+            let mut client = Client {
+                connection,
+                server,
+                next_id: 0,
+            };
+            let result = client
+                .request::<Initialize>(InitializeParams::default())
+                .unwrap();
+            assert_eq!(result.capabilities, capabilities());
+            assert_eq!(result.server_info.unwrap().name, "ssl-lsp");
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: LspRequest>(&mut self, params: R::Params) -> Result<R::Result, String> {
+            self.next_id += 1;
+            let request = Request::new(RequestId::from(self.next_id), R::METHOD.to_owned(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            match self.receive() {
+                Message::Response(response) => {
+                    assert_eq!(response.id, RequestId::from(self.next_id));
+                    if let Some(err) = response.error {
+                        return Err(err.message);
+                    }
+                    let result = response.result.unwrap_or_default();
+                    Ok(serde_json::from_value(result).unwrap())
+                }
+                message => panic!("expected a response, got {message:?}"),
+            }
+        }
+
+        fn notify<N: LspNotification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.to_owned(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn receive(&self) -> Message {
+            self.connection
+                .receiver
+                .recv_timeout(Duration::from_secs(10))
+                .expect("the server didn't answer")
+        }
+
+        fn diagnostics(&self) -> PublishDiagnosticsParams {
+            match self.receive() {
+                Message::Notification(notification) => {
+                    notification.extract(PublishDiagnostics::METHOD).unwrap()
+                }
+                message => panic!("expected diagnostics, got {message:?}"),
+            }
+        }
+
+        fn open(&self, uri: &Url, text: &str) -> PublishDiagnosticsParams {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "ssl".to_owned(),
+                    1,
+                    text.to_owned(),
+                ),
+            });
+            self.diagnostics()
+        }
+
+        fn change(
+            &self,
+            uri: &Url,
+            version: i32,
+            changes: Vec<TextDocumentContentChangeEvent>,
+        ) -> PublishDiagnosticsParams {
+            self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+                content_changes: changes,
+            });
+            self.diagnostics()
+        }
+
+        fn shutdown(mut self) {
+            self.request::<Shutdown>(()).unwrap();
+            self.notify::<Exit>(());
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    fn uri() -> Url {
+        Url::parse("untitled:sheet.ssl").unwrap()
+    }
+
+    fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    fn codes(params: &PublishDiagnosticsParams) -> Vec<NumberOrString> {
+        params
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.code.clone().unwrap())
+            .collect()
+    }
+
+    fn code(code: Code) -> NumberOrString {
+        NumberOrString::String(code.id().to_owned())
+    }
+
+    #[test]
+    fn publishes_on_open() {
+        let client = Client::start();
+        let params = client.open(&uri(), "A1 = 1\nB1 = $vegetables\n");
+        assert_eq!(params.uri, uri());
+        assert_eq!(params.version, Some(1));
+        let [diagnostic] = &params.diagnostics[..] else {
+            panic!("expected one diagnostic, got {:?}", params.diagnostics);
+        };
+        assert_eq!(diagnostic.code, Some(code(Code::UndefinedAlias)));
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(1, 5), Position::new(1, 16))
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn publishes_on_change() {
+        let client = Client::start();
+        let params = client.open(
+            &uri(),
+            "A1 = \"apple\"\nalias fruits = A1:A3\nB1 = enum $friuts\n",
+        );
+        assert_eq!(
+            codes(&params),
+            [code(Code::UndefinedAlias), code(Code::UnusedAlias)]
+        );
+        let unused = &params.diagnostics[1];
+        assert_eq!(unused.severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(unused.tags, Some(vec![DiagnosticTag::UNNECESSARY]));
+
+        // fix the typo, then break the first line
+        let params = client.change(&uri(), 2, vec![edit((2, 13), (2, 15), "ui")]);
+        assert_eq!(params.version, Some(2));
+        assert!(params.diagnostics.is_empty());
+        let params = client.change(&uri(), 3, vec![edit((0, 0), (0, 5), "")]);
+        let errors = codes(&params);
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|c| *c == code(Code::SyntaxError)));
+
+        // several changes apply in order, and a change without a range replaces
+        // the whole text
+        let changes = vec![
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "A1 = 1\n".to_owned(),
+            },
+            edit((1, 0), (1, 0), "B1 = $x\n"),
+        ];
+        let params = client.change(&uri(), 4, changes);
+        assert_eq!(codes(&params), [code(Code::UndefinedAlias)]);
+        client.shutdown();
+    }
+
+    #[test]
+    fn change_inside_character() {
+        let client = Client::start();
+        client.open(&uri(), "A1 = \"😀\"\n");
+        // column 7 is between the two UTF-16 units of the emoji
+        let params = client.change(&uri(), 2, vec![edit((0, 7), (0, 7), "x")]);
+        assert!(params.diagnostics.is_empty());
+        client.shutdown();
+    }
+
+    #[test]
+    fn close_clears_diagnostics() {
+        let client = Client::start();
+        let params = client.open(&uri(), "B1 = $x\n");
+        assert_eq!(params.diagnostics.len(), 1);
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri()),
+        });
+        let params = client.diagnostics();
+        assert!(params.diagnostics.is_empty());
+        assert_eq!(params.version, None);
+        client.shutdown();
+    }
+
+    #[test]
+    fn reloads_config() {
+        let dir = std::env::temp_dir().join(format!("ssl-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join(lint::CONFIG_FILE);
+        std::fs::write(&config, "[lints]\nunused-alias = \"allow\"\n").unwrap();
+        let uri = Url::from_file_path(dir.join("sheet.ssl")).unwrap();
+
+        let client = Client::start();
+        let params = client.open(&uri, "A1 = 1\nalias one = A1\n");
+        assert!(params.diagnostics.is_empty());
+        // the project file is only read again once the client says it changed
+        std::fs::write(&config, "[lints]\nunused-alias = \"deny\"\n").unwrap();
+        let params = client.change(&uri, 2, vec![edit((0, 5), (0, 6), "2")]);
+        assert!(params.diagnostics.is_empty());
+        client.notify::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+            changes: vec![FileEvent::new(
+                Url::from_file_path(&config).unwrap(),
+                FileChangeType::CHANGED,
+            )],
+        });
+        let params = client.diagnostics();
+        assert_eq!(codes(&params), [code(Code::UnusedAlias)]);
+        assert_eq!(
+            params.diagnostics[0].severity,
+            Some(DiagnosticSeverity::ERROR)
+        );
+        client.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn navigation() {
+        let mut client = Client::start();
+        let text = "A1 = \"apple\"\nalias fruits = A1\nB1 = enum $fruits\nC1 = enum $fruits\n";
+        assert!(client.open(&uri(), text).diagnostics.is_empty());
+        let at = |line, character| {
+            TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri()),
+                Position::new(line, character),
+            )
+        };
+        let span = |line, start, end| {
+            Location::new(
+                uri(),
+                Range::new(Position::new(line, start), Position::new(line, end)),
+            )
+        };
+
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: at(2, 12),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        assert_eq!(
+            definition,
+            Ok(Some(GotoDefinitionResponse::Scalar(span(1, 6, 12))))
+        );
+
+        let references = client.request::<References>(ReferenceParams {
+            text_document_position: at(1, 8),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration: false,
+            },
+        });
+        assert_eq!(references, Ok(Some(vec![span(2, 11, 17), span(3, 11, 17)])));
+
+        let rename = |client: &mut Client, new_name: &str| {
+            client.request::<Rename>(RenameParams {
+                text_document_position: at(3, 17),
+                new_name: new_name.to_owned(),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        let edit = rename(&mut client, "produce").unwrap().unwrap();
+        let changes = edit.changes.unwrap();
+        let edits = &changes[&uri()];
+        assert_eq!(edits.len(), 3);
+        assert!(edits.iter().all(|edit| edit.new_text == "produce"));
+        assert_eq!(
+            rename(&mut client, "two words"),
+            Err("`two words` is not a valid name here".to_owned())
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn completion() {
+        let mut client = Client::start();
+        let text = "A1 = \"apple\"\nA2 = \"pear\"\nalias fruits = A1:A2\nB1 = enum $fr";
+        client.open(&uri(), text);
+        let complete = |client: &mut Client, line, character| {
+            let params = CompletionParams {
+                text_document_position: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            };
+            match client.request::<Completion>(params) {
+                Ok(Some(CompletionResponse::Array(items))) => items,
+                response => panic!("expected completions, got {response:?}"),
+            }
+        };
+        let items = complete(&mut client, 3, 13);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].label, "fruits");
+        assert_eq!(items[0].kind, Some(CompletionItemKind::VARIABLE));
+        assert_eq!(
+            items[0].text_edit,
+            Some(CompletionTextEdit::Edit(lsp_types::TextEdit::new(
+                Range::new(Position::new(3, 11), Position::new(3, 13)),
+                "fruits".to_owned(),
+            )))
+        );
+
+        client.change(&uri(), 2, vec![edit((3, 13), (3, 13), "ruits\nB1 = ")]);
+        let labels: Vec<_> = complete(&mut client, 4, 5)
+            .into_iter()
+            .map(|item| item.label)
+            .collect();
+        assert_eq!(labels, ["\"apple\"", "\"pear\""]);
+        client.shutdown();
+    }
+
+    #[test]
+    fn hover() {
+        let mut client = Client::start();
+        client.open(&uri(), "A1 = 3\nA2 = A1\n");
+        let mut hover = |line, character| {
+            client.request::<HoverRequest>(HoverParams {
+                text_document_position_params: TextDocumentPositionParams::new(
+                    TextDocumentIdentifier::new(uri()),
+                    Position::new(line, character),
+                ),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        let a1 = hover(1, 6).unwrap().unwrap();
+        assert_eq!(
+            a1.range,
+            Some(Range::new(Position::new(1, 5), Position::new(1, 7)))
+        );
+        let HoverContents::Markup(contents) = a1.contents else {
+            panic!("expected markdown, got {:?}", a1.contents);
+        };
+        assert_eq!(contents.kind, MarkupKind::Markdown);
+        assert!(contents.value.starts_with("**`A1`**: number = `3`"));
+        // on the `=`
+        assert_eq!(hover(1, 3), Ok(None));
+        client.shutdown();
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let mut client = Client::start();
+        client.open(&uri(), "alias x = A1\n// 𝄞\nB1 = $x\n");
+        let tokens = client.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            text_document: TextDocumentIdentifier::new(uri()),
+        });
+        let Ok(Some(SemanticTokensResult::Tokens(tokens))) = tokens else {
+            panic!("expected semantic tokens, got {tokens:?}");
+        };
+        let token = |delta_line, delta_start, length, class: TokenClass, modifiers: Modifiers| {
+            SemanticToken {
+                delta_line,
+                delta_start,
+                length,
+                token_type: class as u32,
+                token_modifiers_bitset: modifiers.bits(),
+            }
+        };
+        let none = Modifiers::NONE;
+        assert_eq!(
+            tokens.data,
+            [
+                token(0, 0, 5, TokenClass::Keyword, none),
+                token(0, 6, 1, TokenClass::Alias, Modifiers::DEFINITION),
+                token(0, 4, 2, TokenClass::Cell, none),
+                // in UTF-16
+                token(1, 0, 5, TokenClass::Comment, none),
+                token(1, 0, 2, TokenClass::Cell, none),
+                token(0, 5, 2, TokenClass::Alias, none),
+            ]
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn multi_line_semantic_tokens() {
+        let mut client = Client::start();
+        client.open(&uri(), "A1 = \"abc\n\nb\"\nB1 = 1\n");
+        let tokens = client.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            text_document: TextDocumentIdentifier::new(uri()),
+        });
+        let Ok(Some(SemanticTokensResult::Tokens(tokens))) = tokens else {
+            panic!("expected semantic tokens, got {tokens:?}");
+        };
+        let token = |delta_line, delta_start, length, class: TokenClass| SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: class as u32,
+            token_modifiers_bitset: Modifiers::NONE.bits(),
+        };
+        assert_eq!(
+            tokens.data,
+            [
+                token(0, 0, 2, TokenClass::Cell),
+                token(0, 5, 4, TokenClass::String),
+                // nothing on the empty line
+                token(2, 0, 2, TokenClass::String),
+                token(1, 0, 2, TokenClass::Cell),
+                token(0, 5, 1, TokenClass::Number),
+            ]
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn unsupported_request() {
+        let client = Client::start();
+        let request = Request::new(1.into(), "ssl/nonsense".to_owned(), ());
+        client.connection.sender.send(request.into()).unwrap();
+        match client.receive() {
+            Message::Response(response) => {
+                assert_eq!(
+                    response.error.unwrap().code,
+                    ErrorCode::MethodNotFound as i32
+                );
+            }
+            message => panic!("expected a response, got {message:?}"),
+        }
+        client.shutdown();
+    }
+
+    #[test]
+    fn utf16_positions() {
+        // `𝄞` is 4 bytes and 2 UTF-16 code units
+        let source = "A1 = \"𝄞\"\nB1 = 2\n";
+        let index = LineIndex::new(source);
+        let quote = TextSize::from(10);
+        assert_eq!(position(&index, quote), Position::new(0, 8));
+        assert_eq!(offset(&index, Position::new(0, 8)), quote);
+        // past the end of a line, and past the last line
+        assert_eq!(offset(&index, Position::new(1, 100)), TextSize::from(18));
+        assert_eq!(offset(&index, Position::new(5, 0)), index.len());
+    }
+}
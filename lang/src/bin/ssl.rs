@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use lang::diagnostic::{self, Code, Diagnostic};
+use lang::eval::{Evaluated, Evaluator};
+use lang::lint::{self, Config};
+use lang::sema::{Sheet, MAX_RANGE_CELLS};
+use lang::Parse;
+
+const USAGE: &str = "\
+usage: ssl [OPTIONS] <COMMAND> <FILE>...
+       ssl --explain <CODE>
+
+commands:
+    check [--fix]             report syntax and semantic errors, after fixing the
+                              ones that have a safe fix with --fix
+    fmt [--check]             format files in place, or list the unformatted ones
+    eval                      print the value of every cell
+    dump                      print the syntax tree, then check the file
+    export [--out-dir <DIR>]  write each sheet to a CSV file next to it
+
+options:
+    --color=auto|always|never           whether to color diagnostics
+    --message-format=human|json|sarif   how to print diagnostics
+    --output <FILE>                     write json or sarif diagnostics to FILE
+
+Options and the flags of a command can come anywhere in the arguments. FILE can
+also be a glob, like `sheets/*.ssl`. Lint levels are read from the closest
+`ssl.toml` in the directory of each file or above it. `--explain` describes a
+diagnostic code, like SSL0004, in detail.
+
+Human diagnostics and messages are printed to stderr. With --message-format=json,
+diagnostics are printed to stdout as one JSON object per line, and with
+--message-format=sarif as a single SARIF log once all files were checked. Give
+--output to write them to FILE instead, apart from what eval or dump print.";
+
+/// Why a command failed, from least to most severe. When several files fail,
+/// the most severe failure decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Failure {
+    /// `fmt --check` found a file that isn't formatted.
+    Unformatted,
+    Semantic,
+    Parse,
+    Io,
+    /// An invalid project file.
+    Config,
+    Usage,
+}
+
+impl Failure {
+    fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Failure::Unformatted | Failure::Semantic => 1,
+            Failure::Parse => 2,
+            Failure::Io => 3,
+            // EX_CONFIG from sysexits.h
+            Failure::Config => 78,
+            // EX_USAGE from sysexits.h
+            Failure::Usage => 64,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Check,
+    Fmt,
+    Eval,
+    Dump,
+    Export,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MessageFormat {
+    Human,
+    Json,
+    Sarif,
+}
+
+/// What the arguments ask for.
+enum Request {
+    Explain(String),
+    Run(Options),
+}
+
+struct Options {
+    command: Command,
+    format: MessageFormat,
+    check: bool,
+    fix: bool,
+    out_dir: Option<PathBuf>,
+    /// Where JSON or SARIF diagnostics go instead of stdout.
+    output: Option<PathBuf>,
+    files: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Some(Request::Explain(id)) => return explain(&id),
+        Some(Request::Run(options)) => options,
+        None => {
+            eprintln!("{USAGE}");
+            return Failure::Usage.exit_code();
+        }
+    };
+    let output: Box<dyn Write> = match &options.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(err) => return io_error(path, "create", err).exit_code(),
+        },
+        None => Box::new(io::stdout()),
+    };
+    let mut emitter = Emitter {
+        format: options.format,
+        codes: BTreeSet::new(),
+        sarif: Vec::new(),
+        output,
+        error: None,
+    };
+    let mut failure = None;
+    let files = expand(&options.files, &mut failure);
+    let several = files.len() > 1;
+    for (i, path) in files.iter().enumerate() {
+        if options.command == Command::Eval && several {
+            if i > 0 {
+                println!();
+            }
+            println!("{}:", path.display());
+        }
+        if let Err(err) = run(&options, &mut emitter, path) {
+            failure = failure.max(Some(err));
+        }
+    }
+    if let Err(err) = emitter.finish() {
+        let path = options.output.as_deref().unwrap_or(Path::new("<stdout>"));
+        failure = failure.max(Some(io_error(path, "write diagnostics to", err)));
+    }
+    failure.map_or(ExitCode::SUCCESS, Failure::exit_code)
+}
+
+fn explain(id: &str) -> ExitCode {
+    let Some(code) = Code::from_id(id) else {
+        eprintln!("error: `{id}` is not a diagnostic code");
+        return Failure::Usage.exit_code();
+    };
+    println!("# {code}: {}\n", code.name());
+    print!("{}", code.explanation());
+    ExitCode::SUCCESS
+}
+
+/// Parse the arguments in any order, then check that the flags that belong to a
+/// command were given with that command.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Option<Request> {
+    let mut explain = None;
+    let mut command = None;
+    let mut format = MessageFormat::Human;
+    let mut check = false;
+    let mut fix = false;
+    let mut out_dir = None;
+    let mut output = None;
+    let mut files = Vec::new();
+    while let Some(arg) = args.next() {
+        if let Some(color) = arg.strip_prefix("--color=") {
+            match color {
+                "always" => colored::control::set_override(true),
+                "never" => colored::control::set_override(false),
+                "auto" => colored::control::unset_override(),
+                _ => return None,
+            }
+        } else if let Some(name) = arg.strip_prefix("--message-format=") {
+            format = match name {
+                "human" => MessageFormat::Human,
+                "json" => MessageFormat::Json,
+                "sarif" => MessageFormat::Sarif,
+                _ => return None,
+            };
+        } else if arg == "--explain" {
+            explain = Some(args.next()?);
+        } else if let Some(id) = arg.strip_prefix("--explain=") {
+            explain = Some(id.to_owned());
+        } else if arg == "--check" {
+            check = true;
+        } else if arg == "--fix" {
+            fix = true;
+        } else if arg == "--out-dir" {
+            out_dir = Some(args.next()?.into());
+        } else if let Some(dir) = arg.strip_prefix("--out-dir=") {
+            out_dir = Some(dir.into());
+        } else if arg == "--output" {
+            output = Some(args.next()?.into());
+        } else if let Some(file) = arg.strip_prefix("--output=") {
+            output = Some(file.into());
+        } else if arg.starts_with("--") {
+            return None;
+        } else if command.is_none() {
+            command = Some(match arg.as_str() {
+                "check" => Command::Check,
+                "fmt" => Command::Fmt,
+                "eval" => Command::Eval,
+                "dump" => Command::Dump,
+                "export" => Command::Export,
+                _ => return None,
+            });
+        } else {
+            files.push(arg);
+        }
+    }
+    if let Some(id) = explain {
+        return command.is_none().then_some(Request::Explain(id));
+    }
+    let command = command?;
+    let misplaced = (check && command != Command::Fmt)
+        || (fix && command != Command::Check)
+        || (out_dir.is_some() && command != Command::Export)
+        || (output.is_some() && format == MessageFormat::Human);
+    if misplaced || files.is_empty() {
+        return None;
+    }
+    Some(Request::Run(Options {
+        command,
+        format,
+        check,
+        fix,
+        out_dir,
+        output,
+        files,
+    }))
+}
+
+/// Expand the arguments that are globs into the files they match.
+fn expand(args: &[String], failure: &mut Option<Failure>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for arg in args {
+        if !arg.contains(['*', '?', '[']) {
+            files.push(PathBuf::from(arg));
+            continue;
+        }
+        let matches: Vec<_> = match glob::glob(arg) {
+            Ok(paths) => paths.filter_map(Result::ok).collect(),
+            Err(err) => {
+                eprintln!("error: invalid glob `{arg}`: {err}");
+                *failure = (*failure).max(Some(Failure::Usage));
+                continue;
+            }
+        };
+        if matches.is_empty() {
+            eprintln!("error: no files match `{arg}`");
+            *failure = (*failure).max(Some(Failure::Io));
+        }
+        files.extend(matches);
+    }
+    files
+}
+
+fn run(options: &Options, emitter: &mut Emitter, path: &Path) -> Result<(), Failure> {
+    let mut source = std::fs::read_to_string(path).map_err(|err| io_error(path, "read", err))?;
+    let file = path.display().to_string();
+    let config = match options.command {
+        // formatting doesn't lint, so don't make it fail on a bad project file
+        Command::Fmt => Config::default(),
+        _ => config(path)?,
+    };
+    if options.fix {
+        let fixed = lang::fix::fix(&source, &config);
+        if fixed.applied > 0 {
+            std::fs::write(path, &fixed.source).map_err(|err| io_error(path, "write", err))?;
+            let s = if fixed.applied == 1 { "" } else { "s" };
+            eprintln!("fixed {} problem{s} in {file}", fixed.applied);
+            source = fixed.source;
+        }
+    }
+    let mut parse = lang::parse(&source);
+    match options.command {
+        Command::Check => check(emitter, &file, &source, parse, &config).map(drop),
+        Command::Fmt => fmt(path, &source, &parse, options.check),
+        Command::Eval => {
+            let sheet = check(emitter, &file, &source, parse, &config)?;
+            eval(emitter, &file, &source, &sheet)
+        }
+        Command::Dump => {
+            // steal these errors so they don't get printed in the debug output
+            let errors = std::mem::take(&mut parse.errors);
+            print!("{parse:?}");
+            parse.errors = errors;
+            check(emitter, &file, &source, parse, &config).map(drop)
+        }
+        Command::Export => {
+            let sheet = check(emitter, &file, &source, parse, &config)?;
+            export(
+                emitter,
+                path,
+                &file,
+                &source,
+                &sheet,
+                options.out_dir.as_deref(),
+            )
+        }
+    }
+}
+
+/// The lint levels for a file, from the closest project file above it.
+fn config(path: &Path) -> Result<Config, Failure> {
+    let Some(file) = lint::config_file(path) else {
+        return Ok(Config::default());
+    };
+    let text = std::fs::read_to_string(&file).map_err(|err| io_error(&file, "read", err))?;
+    Config::from_toml(&text).map_err(|err| {
+        eprintln!("error: invalid {}: {err}", file.display());
+        Failure::Config
+    })
+}
+
+fn io_error(path: &Path, action: &str, err: io::Error) -> Failure {
+    eprintln!("error: could not {action} {}: {err}", path.display());
+    Failure::Io
+}
+
+/// Report every syntax and semantic problem in a file, and the lints that aren't
+/// allowed. Returns the semantic model if there were no errors, only warnings.
+fn check(
+    emitter: &mut Emitter,
+    file: &str,
+    source: &str,
+    parse: Parse,
+    config: &Config,
+) -> Result<Sheet, Failure> {
+    if !parse.errors.is_empty() {
+        for err in &parse.errors {
+            emitter.emit(file, source, err);
+        }
+        return Err(Failure::Parse);
+    }
+    let sheet = lang::analyze(&parse);
+    // The issues that aren't errors are lints, which have a configurable level.
+    let mut diagnostics: Vec<_> = sheet
+        .issues
+        .iter()
+        .filter(|issue| issue.is_error())
+        .map(|issue| issue.diagnostic(source))
+        .collect();
+    diagnostics.extend(lint::lint(&parse, &sheet, source, config));
+    for diagnostic in &diagnostics {
+        emitter.emit(file, source, diagnostic);
+    }
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(Failure::Semantic);
+    }
+    Ok(sheet)
+}
+
+/// Format a file in place, or with `--check` only report it if it isn't formatted.
+fn fmt(path: &Path, source: &str, parse: &Parse, check: bool) -> Result<(), Failure> {
+    let Some(output) = lang::fmt::format(parse) else {
+        eprintln!(
+            "error: not formatting {} because it has syntax errors",
+            path.display()
+        );
+        return Err(Failure::Parse);
+    };
+    if output == source {
+        return Ok(());
+    }
+    if check {
+        println!("{} is not formatted", path.display());
+        return Err(Failure::Unformatted);
+    }
+    std::fs::write(path, output).map_err(|err| io_error(path, "write", err))
+}
+
+fn eval(emitter: &mut Emitter, file: &str, source: &str, sheet: &Sheet) -> Result<(), Failure> {
+    let mut result = Ok(());
+    // Only report each failing statement once, not once per cell it assigns.
+    let mut reported = HashSet::new();
+    let mut evaluator = Evaluator::new(sheet);
+    for (cell, assignment) in sheet.cells() {
+        let err = match evaluator.eval(cell) {
+            Ok(Some(value)) => {
+                println!("{cell} = {value}");
+                continue;
+            }
+            Ok(None) => continue,
+            Err(err) => err,
+        };
+        result = Err(Failure::Semantic);
+        if !reported.insert(assignment.span) {
+            continue;
+        }
+        emitter.emit(file, source, &err.diagnostic(assignment.span));
+    }
+    result
+}
+
+/// Write the sheet as CSV, with `A1` in the top left corner. Enum cells have no
+/// single value, so they are left empty.
+fn export(
+    emitter: &mut Emitter,
+    path: &Path,
+    file: &str,
+    source: &str,
+    sheet: &Sheet,
+    out_dir: Option<&Path>,
+) -> Result<(), Failure> {
+    let (mut width, mut height) = (0, 0);
+    for (cell, _) in sheet.cells() {
+        let (col, row) = cell.indices();
+        width = width.max(col.saturating_add(1));
+        height = height.max(row.saturating_add(1));
+    }
+    // Every cell up to the last row and column is written out, even empty ones.
+    if width
+        .checked_mul(height)
+        .filter(|&size| size <= MAX_RANGE_CELLS)
+        .is_none()
+    {
+        eprintln!(
+            "error: could not export {}: {width} columns and {height} rows is more than {MAX_RANGE_CELLS} cells",
+            path.display()
+        );
+        return Err(Failure::Semantic);
+    }
+    // Only the cells that have a value, by row and then column.
+    let mut values = BTreeMap::new();
+    let mut result = Ok(());
+    let mut evaluator = Evaluator::new(sheet);
+    for (cell, assignment) in sheet.cells() {
+        let (col, row) = cell.indices();
+        let value = match evaluator.eval(cell) {
+            Ok(Some(Evaluated::Int(n))) => n.to_string(),
+            Ok(Some(Evaluated::Str(s))) => csv_field(&s),
+            Ok(Some(Evaluated::Enum(_)) | None) => continue,
+            Err(err) => {
+                emitter.emit(file, source, &err.diagnostic(assignment.span));
+                result = Err(Failure::Semantic);
+                continue;
+            }
+        };
+        values.insert((row, col), value);
+    }
+    result?;
+
+    let mut csv = String::new();
+    for row in 0..height {
+        for col in 0..width {
+            if col > 0 {
+                csv.push(',');
+            }
+            if let Some(value) = values.get(&(row, col)) {
+                csv.push_str(value);
+            }
+        }
+        csv.push('\n');
+    }
+    let dir = out_dir.or(path.parent()).unwrap_or(Path::new(""));
+    let out = dir.join(path.with_extension("csv").file_name().unwrap());
+    std::fs::write(&out, csv).map_err(|err| io_error(&out, "write", err))
+}
+
+/// Quote a CSV field if it contains anything that would be misread otherwise.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Where diagnostics go, depending on `--message-format`.
+struct Emitter {
+    format: MessageFormat,
+    /// The codes of the diagnostics shown so far, to point at `--explain`.
+    codes: BTreeSet<Code>,
+    /// SARIF results, which can only be printed once all files were checked.
+    sarif: Vec<serde_json::Value>,
+    /// Stdout or the `--output` file, for JSON and SARIF.
+    output: Box<dyn Write>,
+    /// The first error writing to `output`, reported by `finish`.
+    error: Option<io::Error>,
+}
+
+impl Emitter {
+    fn emit(&mut self, file: &str, source: &str, diagnostic: &Diagnostic) {
+        match self.format {
+            MessageFormat::Human => {
+                let color = colored::control::SHOULD_COLORIZE.should_colorize();
+                eprintln!("{}", diagnostic.render(file, source, color));
+                self.codes.insert(diagnostic.code);
+            }
+            MessageFormat::Json => {
+                let json = diagnostic.to_json(file, source);
+                if let Err(err) = writeln!(self.output, "{json}") {
+                    self.error.get_or_insert(err);
+                }
+            }
+            MessageFormat::Sarif => self.sarif.push(diagnostic.to_sarif(file, source)),
+        }
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        match self.format {
+            MessageFormat::Human => {
+                let mut codes = self.codes.iter();
+                match (codes.next(), codes.len()) {
+                    (None, _) => {}
+                    (Some(code), 0) => eprintln!(
+                        "For more information about this diagnostic, try `ssl --explain {code}`."
+                    ),
+                    (Some(code), _) => {
+                        let all: Vec<_> = self.codes.iter().map(Code::id).collect();
+                        eprintln!(
+                            "Some diagnostics have detailed explanations: {}.",
+                            all.join(", ")
+                        );
+                        eprintln!(
+                            "For more information about a diagnostic, try `ssl --explain {code}`."
+                        );
+                    }
+                }
+            }
+            MessageFormat::Json => {}
+            MessageFormat::Sarif => {
+                let log = diagnostic::sarif_log(std::mem::take(&mut self.sarif));
+                if let Err(err) = writeln!(self.output, "{log}") {
+                    self.error.get_or_insert(err);
+                }
+            }
+        }
+        match self.error {
+            Some(err) => Err(err),
+            None => self.output.flush(),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Rewrite a file into a canonical statement order, so that two saves of the same
+//! sheet produce the same text no matter in which order its statements were written.
+//!
+//! The canonical order is:
+//! 1. comments at the top of the file that are followed by a blank line, like `//@`
+//!    directives
+//! 2. `alias` statements, in their original order, since an alias can refer to an
+//!    earlier one
+//! 3. `enum` assignments, column by column
+//! 4. all other assignments, column by column
+//! 5. comments after the last statement
+//!
+//! Any other comment stays attached to the statement below it. Statements are
+//! formatted like [`crate::fmt`] does.
+//!
+//! Assignments whose targets overlap are never reordered relative to each other,
+//! since the last one wins, and neither is an assignment that reads a cell relative
+//! to one that writes it, since whether a value was read before being overwritten
+//! decides what is reported about it. Files that define the same alias name twice
+//! aren't canonicalized at all, since hoisting the second definition would change
+//! what the uses between them refer to.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use cstree::util::NodeOrToken;
+
+use crate::cell_index::RangeRef;
+use crate::fmt::{self, Line};
+use crate::parser::{Parse, SyntaxKind::*};
+use crate::sema::{self, Issue, Value};
+
+struct Statement {
+    comments: Vec<Line>,
+    line: Line,
+    target: Option<RangeRef>,
+    /// The cells the value reads, for references and enums.
+    read: Option<RangeRef>,
+    is_enum: bool,
+}
+
+impl Statement {
+    fn key(&self) -> (bool, Option<(u128, u128, u128, u128)>) {
+        let target = self.target.map(|target| {
+            let (start_col, start_row) = target.start().indices();
+            let (end_col, end_row) = target.end().indices();
+            (start_col, start_row, end_col, end_row)
+        });
+        (!self.is_enum, target)
+    }
+}
+
+/// Rewrite a parsed file into canonical order.
+///
+/// Returns `None` if the file has errors or redefines an alias, since then we
+/// can't tell which statements depend on each other.
+pub fn canonicalize(parse: &Parse) -> Option<String> {
+    if !parse.errors.is_empty() {
+        return None;
+    }
+    let sheet = sema::analyze(parse);
+    if sheet.issues.iter().any(Issue::is_error) {
+        return None;
+    }
+    let assignments: HashMap<_, _> = sheet
+        .assignments
+        .iter()
+        .map(|assignment| (assignment.span.start(), assignment))
+        .collect();
+
+    let mut header = Vec::new();
+    let mut comments = Vec::new();
+    let mut aliases = Vec::new();
+    let mut names = HashSet::new();
+    let mut statements = Vec::new();
+    let root = parse.red_tree();
+    for elem in root.children_with_tokens() {
+        match elem {
+            NodeOrToken::Node(stmt) => {
+                let comments = std::mem::take(&mut comments);
+                let line = fmt::statement(parse, stmt);
+                let Some(inner) = stmt.first_child() else {
+                    continue;
+                };
+                if inner.kind() == ALIAS_STMT {
+                    let name = inner
+                        .children_with_tokens()
+                        .filter_map(|elem| elem.into_token())
+                        .find(|t| t.kind() == IDENT);
+                    if name.is_some_and(|name| !names.insert(parse.text(name))) {
+                        return None;
+                    }
+                    aliases.push((comments, line));
+                    continue;
+                }
+                let assignment = inner
+                    .first_child()
+                    .and_then(|place| assignments.get(&sema::trimmed_range(place).start()));
+                statements.push(Statement {
+                    comments,
+                    line,
+                    target: assignment.map(|a| a.target),
+                    read: assignment.and_then(|a| match a.value {
+                        Value::Enum(range) | Value::Ref(range) => Some(range),
+                        Value::Int(_) | Value::Str(_) => None,
+                    }),
+                    is_enum: assignment.is_some_and(|a| matches!(a.value, Value::Enum(_))),
+                });
+            }
+            // A blank line after the leading comments ends the header.
+            NodeOrToken::Token(token) if token.kind() == NEWLINE => {
+                if aliases.is_empty() && statements.is_empty() {
+                    header.append(&mut comments);
+                }
+            }
+            NodeOrToken::Token(token) if token.kind() == COMMENT => {
+                comments.push(fmt::comment(parse, token))
+            }
+            NodeOrToken::Token(_) => {}
+        }
+    }
+    let footer = comments;
+
+    let mut lines = header;
+    lines.push(Line::Blank);
+    for (comments, line) in aliases {
+        lines.extend(comments);
+        lines.push(line);
+    }
+
+    // Sort topologically, so that a statement is only emitted once every earlier
+    // statement it depends on was. Among the ones that are ready, pick the
+    // smallest key.
+    let overlap =
+        |a: Option<RangeRef>, b: Option<RangeRef>| a.zip(b).is_some_and(|(a, b)| a.intersects(b));
+    let mut blockers = vec![0; statements.len()];
+    let mut blocks = vec![Vec::new(); statements.len()];
+    for (i, a) in statements.iter().enumerate() {
+        for (j, b) in statements.iter().enumerate().skip(i + 1) {
+            if overlap(a.target, b.target) || overlap(a.read, b.target) || overlap(a.target, b.read)
+            {
+                blockers[j] += 1;
+                blocks[i].push(j);
+            }
+        }
+    }
+    let mut ready: BTreeSet<_> = (0..statements.len())
+        .filter(|&i| blockers[i] == 0)
+        .map(|i| (statements[i].key(), i))
+        .collect();
+    let mut order = Vec::with_capacity(statements.len());
+    while let Some((_, i)) = ready.pop_first() {
+        order.push(i);
+        for &j in &blocks[i] {
+            blockers[j] -= 1;
+            if blockers[j] == 0 {
+                ready.insert((statements[j].key(), j));
+            }
+        }
+    }
+
+    let mut statements: Vec<_> = statements.into_iter().map(Some).collect();
+    let mut section = None;
+    for i in order {
+        let stmt = statements[i].take().unwrap();
+        if section != Some(stmt.is_enum) {
+            lines.push(Line::Blank);
+            section = Some(stmt.is_enum);
+        }
+        lines.extend(stmt.comments);
+        lines.push(stmt.line);
+    }
+    lines.push(Line::Blank);
+    lines.extend(footer);
+    Some(fmt::render(&lines))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[track_caller]
+    fn check(input: &str, expected: &str) {
+        let canonical = canonicalize(&crate::parse(input)).unwrap();
+        assert_eq!(canonical, expected);
+    }
+
+    #[test]
+    fn order_independent() {
+        let expected = "//@ exit-status: 0\n\nalias x = A1\n\nC1 = enum A1:A3\n\n// the fruit\nA1 = \"apple\"\nA2 = 2\nB2 = 1\n";
+        check(
+            "//@ exit-status: 0\n\nB2 = 1\n// the fruit\nA1 = \"apple\"\nalias x = A1\nC1 = enum A1:A3\nA2 = 2\n",
+            expected,
+        );
+        check(
+            "//@ exit-status: 0\n\nalias x = A1\nA2=2\nC1 = enum A1:A3\n\n// the fruit\nA1 = \"apple\"\nB2 = 1\n",
+            expected,
+        );
+    }
+
+    #[test]
+    fn overlapping() {
+        check("A2 = 5\nA1:A3 = 1\n", "A2    = 5\nA1:A3 = 1\n");
+        check("A1:A3 = 1\nA2 = 5\n", "A1:A3 = 1\nA2    = 5\n");
+        check(
+            "B1 = 1\nA2 = 5\nA1:A3 = 1\n",
+            "A2    = 5\nA1:A3 = 1\nB1    = 1\n",
+        );
+    }
+
+    #[test]
+    fn reads() {
+        // `E1` reads the first `D1`, so moving it after the second would report
+        // that `D1 = 0` is overwritten.
+        check("D1 = 0\nE1 = D1\nD1 = 3\n", "D1 = 0\nE1 = D1\nD1 = 3\n");
+        check("B1 = A1\nA1 = 1\n", "B1 = A1\nA1 = 1\n");
+    }
+
+    #[test]
+    fn same_diagnostics() {
+        let diagnostics = |source: &str| {
+            let parse = crate::parse(source);
+            let sheet = sema::analyze(&parse);
+            let config = crate::lint::Config::default();
+            let mut found: Vec<_> = sheet
+                .issues
+                .iter()
+                .map(|issue| issue.diagnostic(source))
+                .chain(crate::lint::lint(&parse, &sheet, source, &config))
+                .map(|d| (d.code, d.message))
+                .collect();
+            found.sort();
+            found
+        };
+        for source in [
+            "D1 = 0\nE1 = D1\nD1 = 3\n",
+            "B2 = 1\nA1:A2 = 0\nC1 = A1:A2\nA1 = 5\n",
+            "A1 = \"x\"\nB1 = enum A1:A2\nA2 = \"y\"\nA1 = \"z\"\n",
+            "alias x = A1\nB1 = $x\nA1 = 2\nA1 = 3\n",
+        ] {
+            let canonical = canonicalize(&crate::parse(source)).unwrap();
+            assert_eq!(diagnostics(&canonical), diagnostics(source), "{source}");
+        }
+    }
+
+    #[test]
+    fn redefined_alias() {
+        let parse = crate::parse("alias x = A1\nB1 = $x\nalias x = A2\nB2 = $x\n");
+        assert_eq!(canonicalize(&parse), None);
+    }
+}
@@ -0,0 +1,362 @@
+//! Cell coordinates in the `A1` notation used by `CELL` tokens.
+//!
+//! Rows are displayed one-based (`1`, `2`, ...) and columns as letters (`A`, `B`,
+//! ...). Both can be converted to and from zero-based indices.
+
+use std::fmt::{self, Display};
+use std::num::NonZeroU128;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Row(NonZeroU128);
+
+impl Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+impl Add<u128> for Row {
+    type Output = Option<Row>;
+
+    fn add(self, rhs: u128) -> Self::Output {
+        self.0.checked_add(rhs).map(Row)
+    }
+}
+
+impl Sub<u128> for Row {
+    type Output = Option<Row>;
+
+    fn sub(self, rhs: u128) -> Self::Output {
+        self.0
+            .get()
+            .checked_sub(rhs)
+            .and_then(NonZeroU128::new)
+            .map(Row)
+    }
+}
+
+impl Row {
+    pub const FIRST: Self = Self(NonZeroU128::MIN);
+
+    /// The row with the given zero-based index.
+    pub fn from_index(index: u128) -> Option<Self> {
+        Self::FIRST + index
+    }
+
+    pub fn index(self) -> u128 {
+        self.0.get() - 1
+    }
+}
+
+impl FromStr for Row {
+    type Err = ParseCellError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `u128::from_str` also accepts a leading `+`
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseCellError);
+        }
+        s.parse()
+            .ok()
+            .and_then(NonZeroU128::new)
+            .map(Row)
+            .ok_or(ParseCellError)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Col(u128);
+
+impl Add<u128> for Col {
+    type Output = Option<Col>;
+
+    fn add(self, rhs: u128) -> Self::Output {
+        self.0.checked_add(rhs).map(Col)
+    }
+}
+
+impl Sub<u128> for Col {
+    type Output = Option<Col>;
+
+    fn sub(self, rhs: u128) -> Self::Output {
+        self.0.checked_sub(rhs).map(Col)
+    }
+}
+
+impl Col {
+    pub const FIRST: Self = Self(0);
+
+    /// The column with the given zero-based index.
+    pub fn from_index(index: u128) -> Self {
+        Self(index)
+    }
+
+    pub fn index(self) -> u128 {
+        self.0
+    }
+}
+
+impl Display for Col {
+    /// Columns are named in bijective base 26: `A` to `Z`, then `AA` to `AZ`, `BA` and
+    /// so on. Unlike plain base 26 there is no zero digit, so `A` never acts as a
+    /// leading zero.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = String::new();
+        let mut col = self.0;
+        loop {
+            let rem = (col % 26) as u8;
+            s.insert(0, (b'A' + rem).into());
+            if col < 26 {
+                return f.write_str(&s);
+            }
+            col = col / 26 - 1;
+        }
+    }
+}
+
+impl FromStr for Col {
+    type Err = ParseCellError;
+
+    /// Parse a column name, ignoring case. This is the inverse of `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut col: Option<u128> = None;
+        for b in s.bytes() {
+            if !b.is_ascii_alphabetic() {
+                return Err(ParseCellError);
+            }
+            let digit = u128::from(b.to_ascii_uppercase() - b'A');
+            // Accumulate zero-based so that the last column still fits in a `u128`.
+            col = Some(match col {
+                None => digit,
+                Some(col) => col
+                    .checked_add(1)
+                    .and_then(|col| col.checked_mul(26))
+                    .and_then(|col| col.checked_add(digit))
+                    .ok_or(ParseCellError)?,
+            });
+        }
+        col.map(Col).ok_or(ParseCellError)
+    }
+}
+
+/// A single cell, like `B3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CellRef {
+    pub col: Col,
+    pub row: Row,
+}
+
+impl CellRef {
+    pub fn new(col: Col, row: Row) -> Self {
+        Self { col, row }
+    }
+
+    /// The cell with the given zero-based column and row indices.
+    pub fn from_indices(col: u128, row: u128) -> Option<Self> {
+        Some(Self::new(Col::from_index(col), Row::from_index(row)?))
+    }
+
+    /// The zero-based `(column, row)` indices of this cell.
+    pub fn indices(self) -> (u128, u128) {
+        (self.col.index(), self.row.index())
+    }
+}
+
+impl Display for CellRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.col, self.row)
+    }
+}
+
+impl FromStr for CellRef {
+    type Err = ParseCellError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| c.is_ascii_digit()).ok_or(ParseCellError)?;
+        let (col, row) = s.split_at(split);
+        Ok(Self::new(col.parse()?, row.parse()?))
+    }
+}
+
+/// A rectangular block of cells, like `A1:B3`, inclusive on both ends.
+///
+/// The range is always normalised so that `start` is the top left cell and `end`
+/// the bottom right one, which means `B3:A1` and `A1:B3` are the same range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RangeRef {
+    start: CellRef,
+    end: CellRef,
+}
+
+impl RangeRef {
+    /// The smallest range containing both cells, regardless of their order.
+    pub fn new(a: CellRef, b: CellRef) -> Self {
+        Self {
+            start: CellRef::new(a.col.min(b.col), a.row.min(b.row)),
+            end: CellRef::new(a.col.max(b.col), a.row.max(b.row)),
+        }
+    }
+
+    pub fn start(self) -> CellRef {
+        self.start
+    }
+
+    pub fn end(self) -> CellRef {
+        self.end
+    }
+
+    pub fn contains(self, cell: CellRef) -> bool {
+        (self.start.col..=self.end.col).contains(&cell.col)
+            && (self.start.row..=self.end.row).contains(&cell.row)
+    }
+
+    /// The number of cells in this range, or `None` if that doesn't fit in a
+    /// `u128`.
+    pub fn size(self) -> Option<u128> {
+        let (start_col, start_row) = self.start.indices();
+        let (end_col, end_row) = self.end.indices();
+        let cols = (end_col - start_col).checked_add(1)?;
+        let rows = (end_row - start_row).checked_add(1)?;
+        cols.checked_mul(rows)
+    }
+
+    pub fn intersects(self, other: RangeRef) -> bool {
+        self.start.col <= other.end.col
+            && other.start.col <= self.end.col
+            && self.start.row <= other.end.row
+            && other.start.row <= self.end.row
+    }
+
+    /// All cells in this range, row by row.
+    pub fn cells(self) -> impl Iterator<Item = CellRef> {
+        let (start_col, start_row) = self.start.indices();
+        let (end_col, end_row) = self.end.indices();
+        (start_row..=end_row).flat_map(move |row| {
+            (start_col..=end_col)
+                .map(move |col| CellRef::new(Col(col), Row::from_index(row).unwrap()))
+        })
+    }
+}
+
+impl From<CellRef> for RangeRef {
+    fn from(cell: CellRef) -> Self {
+        Self::new(cell, cell)
+    }
+}
+
+impl Display for RangeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.start, self.end)
+    }
+}
+
+impl FromStr for RangeRef {
+    type Err = ParseCellError;
+
+    /// Parse `A1:B3`, or a single cell like `A1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((start, end)) => Ok(Self::new(start.parse()?, end.parse()?)),
+            None => Ok(s.parse::<CellRef>()?.into()),
+        }
+    }
+}
+
+/// The error returned when a row, column, cell or range name is malformed or out of range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCellError;
+
+impl Display for ParseCellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid cell reference")
+    }
+}
+
+impl std::error::Error for ParseCellError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::SyntaxKind;
+    use proptest::prelude::*;
+
+    fn cell(s: &str) -> CellRef {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cells() {
+        assert_eq!(cell("A1").indices(), (0, 0));
+        assert_eq!(cell("z3").indices(), (25, 2));
+        assert_eq!(cell("AA123").indices(), (26, 122));
+        assert_eq!(cell("AZ1").indices(), (51, 0));
+        assert_eq!(cell("BA1").indices(), (52, 0));
+        for bad in ["", "A", "1", "A0", "A+1", "1A", "A1B", "Ä1"] {
+            assert_eq!(bad.parse::<CellRef>(), Err(ParseCellError), "{bad}");
+        }
+    }
+
+    #[test]
+    fn col_names() {
+        let names = [
+            (0, "A"),
+            (25, "Z"),
+            (26, "AA"),
+            (51, "AZ"),
+            (52, "BA"),
+            (701, "ZZ"),
+            (702, "AAA"),
+            (18277, "ZZZ"),
+        ];
+        for (index, name) in names {
+            assert_eq!(Col::from_index(index).to_string(), name);
+            assert_eq!(name.parse(), Ok(Col::from_index(index)));
+        }
+        let last = Col::from_index(u128::MAX);
+        assert_eq!(last.to_string().parse(), Ok(last));
+        assert_eq!(format!("{last}A").parse::<Col>(), Err(ParseCellError));
+    }
+
+    proptest! {
+        #[test]
+        fn col_round_trip(index: u128) {
+            let col = Col::from_index(index);
+            prop_assert_eq!(col.to_string().parse(), Ok(col));
+        }
+
+        #[test]
+        fn cell_token_round_trip(col in 0..1_000_000u128, row in 0..1_000_000u128) {
+            let cell = CellRef::from_indices(col, row).unwrap();
+            let name = cell.to_string();
+            let source = format!("{name} = 0\n");
+            let parse = crate::parse(&source);
+            prop_assert!(parse.errors.is_empty());
+            let tree = parse.red_tree();
+            let token = tree
+                .descendants_with_tokens()
+                .filter_map(|elem| elem.into_token())
+                .find(|token| token.kind() == SyntaxKind::CELL)
+                .unwrap();
+            prop_assert_eq!(parse.text(token), name.as_str());
+            prop_assert_eq!(parse.text(token).parse(), Ok(cell));
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let range: RangeRef = "B3:A1".parse().unwrap();
+        assert_eq!(range, "A1:B3".parse().unwrap());
+        assert_eq!(range.to_string(), "A1:B3");
+        let cells: Vec<_> = range.cells().map(|c| c.to_string()).collect();
+        assert_eq!(cells, ["A1", "B1", "A2", "B2", "A3", "B3"]);
+        assert!(range.contains(cell("B2")));
+        assert!(!range.contains(cell("C2")));
+        assert!(range.intersects("B3:C4".parse().unwrap()));
+        assert!(!range.intersects("C1:C4".parse().unwrap()));
+        assert_eq!("C4".parse::<RangeRef>().unwrap().cells().count(), 1);
+        assert_eq!(range.size(), Some(6));
+        let huge = RangeRef::new(cell("A1"), CellRef::new(Col(u128::MAX), Row::FIRST));
+        assert_eq!(huge.size(), None);
+    }
+}
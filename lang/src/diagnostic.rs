@@ -0,0 +1,612 @@
+//! Errors and warnings about a file, independent of how they are shown.
+//!
+//! Every pass, starting with the parser, reports problems as a [`Diagnostic`].
+//! [`Diagnostic::render`] shows them to humans; the JSON and SARIF forms are for
+//! other tools.
+
+use std::fmt;
+use std::ops::Range;
+
+use annotate_snippets::{Level, Renderer, Snippet};
+use cstree::text::TextRange;
+#[cfg(feature = "cli")]
+use cstree::text::TextSize;
+#[cfg(feature = "cli")]
+use serde_json::{json, Value};
+
+#[cfg(feature = "cli")]
+use crate::line_index::{LineIndex, WideEncoding};
+
+/// The kind of a diagnostic, with a stable identifier like `SSL0004` and a
+/// long-form explanation for `ssl --explain`.
+///
+/// Codes are never reused: when a diagnostic goes away, its code is retired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Code {
+    SyntaxError,
+    InvalidCell,
+    IntOutOfRange,
+    UndefinedAlias,
+    ShadowedAssignment,
+    Cycle,
+    ShapeMismatch,
+    RangeTooLarge,
+    EnumMemberCase,
+    UnusedAlias,
+    EmptyEnum,
+    InconsistentColumnTypes,
+    HardCodedNumber,
+    UnknownLint,
+    DeletedReference,
+    SheetTooLarge,
+}
+
+impl Code {
+    pub const ALL: &'static [Code] = &[
+        Code::SyntaxError,
+        Code::InvalidCell,
+        Code::IntOutOfRange,
+        Code::UndefinedAlias,
+        Code::ShadowedAssignment,
+        Code::Cycle,
+        Code::ShapeMismatch,
+        Code::RangeTooLarge,
+        Code::EnumMemberCase,
+        Code::UnusedAlias,
+        Code::EmptyEnum,
+        Code::InconsistentColumnTypes,
+        Code::HardCodedNumber,
+        Code::UnknownLint,
+        Code::DeletedReference,
+        Code::SheetTooLarge,
+    ];
+
+    /// The stable identifier, like `SSL0004`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Code::SyntaxError => "SSL0001",
+            Code::InvalidCell => "SSL0002",
+            Code::IntOutOfRange => "SSL0003",
+            Code::UndefinedAlias => "SSL0004",
+            Code::ShadowedAssignment => "SSL0005",
+            Code::Cycle => "SSL0006",
+            Code::ShapeMismatch => "SSL0007",
+            Code::RangeTooLarge => "SSL0008",
+            Code::EnumMemberCase => "SSL0009",
+            Code::UnusedAlias => "SSL0010",
+            Code::EmptyEnum => "SSL0011",
+            Code::InconsistentColumnTypes => "SSL0012",
+            Code::HardCodedNumber => "SSL0013",
+            Code::UnknownLint => "SSL0014",
+            Code::DeletedReference => "SSL0015",
+            Code::SheetTooLarge => "SSL0016",
+        }
+    }
+
+    /// A short human-readable name, like `undefined-alias`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::SyntaxError => "syntax-error",
+            Code::InvalidCell => "invalid-cell",
+            Code::IntOutOfRange => "int-out-of-range",
+            Code::UndefinedAlias => "undefined-alias",
+            Code::ShadowedAssignment => "shadowed-assignment",
+            Code::Cycle => "cycle",
+            Code::ShapeMismatch => "shape-mismatch",
+            Code::RangeTooLarge => "range-too-large",
+            Code::EnumMemberCase => "enum-member-case",
+            Code::UnusedAlias => "unused-alias",
+            Code::EmptyEnum => "empty-enum",
+            Code::InconsistentColumnTypes => "inconsistent-column-types",
+            Code::HardCodedNumber => "hard-coded-number",
+            Code::UnknownLint => "unknown-lint",
+            Code::DeletedReference => "deleted-reference",
+            Code::SheetTooLarge => "sheet-too-large",
+        }
+    }
+
+    /// The long-form explanation, in Markdown, with examples.
+    pub fn explanation(self) -> &'static str {
+        match self {
+            Code::SyntaxError => include_str!("explanations/SSL0001.md"),
+            Code::InvalidCell => include_str!("explanations/SSL0002.md"),
+            Code::IntOutOfRange => include_str!("explanations/SSL0003.md"),
+            Code::UndefinedAlias => include_str!("explanations/SSL0004.md"),
+            Code::ShadowedAssignment => include_str!("explanations/SSL0005.md"),
+            Code::Cycle => include_str!("explanations/SSL0006.md"),
+            Code::ShapeMismatch => include_str!("explanations/SSL0007.md"),
+            Code::RangeTooLarge => include_str!("explanations/SSL0008.md"),
+            Code::EnumMemberCase => include_str!("explanations/SSL0009.md"),
+            Code::UnusedAlias => include_str!("explanations/SSL0010.md"),
+            Code::EmptyEnum => include_str!("explanations/SSL0011.md"),
+            Code::InconsistentColumnTypes => include_str!("explanations/SSL0012.md"),
+            Code::HardCodedNumber => include_str!("explanations/SSL0013.md"),
+            Code::UnknownLint => include_str!("explanations/SSL0014.md"),
+            Code::DeletedReference => include_str!("explanations/SSL0015.md"),
+            Code::SheetTooLarge => include_str!("explanations/SSL0016.md"),
+        }
+    }
+
+    /// Look up a code by its identifier, ignoring case.
+    pub fn from_id(id: &str) -> Option<Code> {
+        Code::ALL
+            .iter()
+            .copied()
+            .find(|code| code.id().eq_ignore_ascii_case(id))
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: TextRange,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: TextRange, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// How sure we are that a [`Suggestion`] is what the user meant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    /// Can be applied without looking, e.g. by `ssl check --fix`.
+    MachineApplicable,
+    /// Probably right, but a human should check it.
+    MaybeIncorrect,
+}
+
+impl fmt::Display for Applicability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Applicability::MachineApplicable => "machine-applicable",
+            Applicability::MaybeIncorrect => "maybe-incorrect",
+        })
+    }
+}
+
+/// A change to the source that would fix the problem: replace the text at `span`
+/// with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub message: String,
+    pub span: TextRange,
+    pub replacement: String,
+    pub applicability: Applicability,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: Code,
+    pub severity: Severity,
+    pub message: String,
+    /// Where the problem is.
+    pub primary: Label,
+    /// Other places that help explain it.
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, message: impl Into<String>, primary: Label) -> Self {
+        Self::new(code, Severity::Error, message.into(), primary)
+    }
+
+    pub fn warning(code: Code, message: impl Into<String>, primary: Label) -> Self {
+        Self::new(code, Severity::Warning, message.into(), primary)
+    }
+
+    fn new(code: Code, severity: Severity, message: String, primary: Label) -> Self {
+        Self {
+            code,
+            severity,
+            message,
+            primary,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.secondary.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Suggest a change that a human should look at before applying it.
+    pub fn with_suggestion(
+        self,
+        message: impl Into<String>,
+        span: TextRange,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.suggest(Applicability::MaybeIncorrect, message, span, replacement)
+    }
+
+    /// Suggest a change that is safe to apply automatically.
+    pub fn with_fix(
+        self,
+        message: impl Into<String>,
+        span: TextRange,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.suggest(Applicability::MachineApplicable, message, span, replacement)
+    }
+
+    fn suggest(
+        mut self,
+        applicability: Applicability,
+        message: impl Into<String>,
+        span: TextRange,
+        replacement: impl Into<String>,
+    ) -> Self {
+        self.suggestions.push(Suggestion {
+            message: message.into(),
+            span,
+            replacement: replacement.into(),
+            applicability,
+        });
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// The primary label followed by the secondary ones.
+    pub fn labels(&self) -> impl Iterator<Item = &Label> {
+        std::iter::once(&self.primary).chain(&self.secondary)
+    }
+
+    /// Replace every span of this diagnostic, like when the text it points to
+    /// moved.
+    pub(crate) fn map_spans(mut self, f: impl Fn(TextRange) -> TextRange) -> Self {
+        self.primary.span = f(self.primary.span);
+        for label in &mut self.secondary {
+            label.span = f(label.span);
+        }
+        for suggestion in &mut self.suggestions {
+            suggestion.span = f(suggestion.span);
+        }
+        self
+    }
+
+    /// Render this diagnostic for humans, quoting the lines of `source` that it
+    /// points to. The primary label is underlined with `^` for errors; every other
+    /// label with `-`.
+    pub fn render(&self, file: &str, source: &str, color: bool) -> String {
+        let level = match self.severity {
+            Severity::Error => Level::Error,
+            Severity::Warning => Level::Warning,
+        };
+        let range = |span: TextRange| Range::<usize>::from(span);
+        let primary = level
+            .span(range(self.primary.span))
+            .label(&self.primary.message);
+        // annotate-snippets only prefixes a label with its level if that differs
+        // from the message's, and only draws `^` for errors.
+        let secondary = self
+            .secondary
+            .iter()
+            .map(|label| Level::Warning.span(range(label.span)).label(&label.message));
+        let snippet = Snippet::source(source)
+            .fold(true)
+            .origin(file)
+            .annotations(std::iter::once(primary).chain(secondary));
+
+        let suggestions: Vec<_> = self
+            .suggestions
+            .iter()
+            .map(|suggestion| match suggestion.replacement.trim() {
+                // e.g. adding a newline, which the message already says
+                "" => suggestion.message.clone(),
+                replacement => format!("{}: `{replacement}`", suggestion.message),
+            })
+            .collect();
+        let mut msg = level
+            .title(&self.message)
+            .id(self.code.id())
+            .snippet(snippet);
+        for note in &self.notes {
+            msg = msg.footer(Level::Note.title(note));
+        }
+        for suggestion in &suggestions {
+            msg = msg.footer(Level::Help.title(suggestion));
+        }
+
+        let renderer = if color {
+            Renderer::styled()
+        } else {
+            Renderer::plain()
+        };
+        renderer.render(msg).to_string()
+    }
+
+    /// This diagnostic as a JSON object, with spans both as byte offsets and as
+    /// one-based lines and columns (counted in characters, end exclusive).
+    #[cfg(feature = "cli")]
+    pub fn to_json(&self, file: &str, source: &str) -> Value {
+        let index = LineIndex::new(source);
+        let spans: Vec<_> = self
+            .labels()
+            .enumerate()
+            .map(|(i, label)| {
+                let (line_start, column_start) = line_col(&index, label.span.start());
+                let (line_end, column_end) = line_col(&index, label.span.end());
+                json!({
+                    "file": file,
+                    "byte_start": usize::from(label.span.start()),
+                    "byte_end": usize::from(label.span.end()),
+                    "line_start": line_start,
+                    "column_start": column_start,
+                    "line_end": line_end,
+                    "column_end": column_end,
+                    "is_primary": i == 0,
+                    "label": label.message,
+                })
+            })
+            .collect();
+        let suggestions: Vec<_> = self
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                json!({
+                    "message": suggestion.message,
+                    "byte_start": usize::from(suggestion.span.start()),
+                    "byte_end": usize::from(suggestion.span.end()),
+                    "replacement": suggestion.replacement,
+                    "applicability": suggestion.applicability.to_string(),
+                })
+            })
+            .collect();
+        json!({
+            "code": self.code.id(),
+            "name": self.code.name(),
+            "severity": self.severity.to_string(),
+            "message": self.message,
+            "spans": spans,
+            "notes": self.notes,
+            "suggestions": suggestions,
+        })
+    }
+
+    /// This diagnostic as a SARIF `result` object, to be collected with [`sarif_log`].
+    #[cfg(feature = "cli")]
+    pub fn to_sarif(&self, file: &str, source: &str) -> Value {
+        let index = LineIndex::new(source);
+        let region = |span: TextRange| {
+            let (start_line, start_column) = line_col(&index, span.start());
+            let (end_line, end_column) = line_col(&index, span.end());
+            json!({
+                "startLine": start_line,
+                "startColumn": start_column,
+                "endLine": end_line,
+                "endColumn": end_column,
+                "byteOffset": usize::from(span.start()),
+                "byteLength": usize::from(span.len()),
+            })
+        };
+        let location = |label: &Label| {
+            json!({
+                "physicalLocation": {
+                    "artifactLocation": { "uri": file },
+                    "region": region(label.span),
+                },
+                "message": { "text": label.message },
+            })
+        };
+        let fixes: Vec<_> = self
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                json!({
+                    "description": { "text": suggestion.message },
+                    "artifactChanges": [{
+                        "artifactLocation": { "uri": file },
+                        "replacements": [{
+                            "deletedRegion": region(suggestion.span),
+                            "insertedContent": { "text": suggestion.replacement },
+                        }],
+                    }],
+                })
+            })
+            .collect();
+        let related: Vec<_> = self.secondary.iter().map(location).collect();
+        let mut text = self.message.clone();
+        for note in &self.notes {
+            text.push_str("\nnote: ");
+            text.push_str(note);
+        }
+        json!({
+            "ruleId": self.code.id(),
+            "level": self.severity.to_string(),
+            "message": { "text": text },
+            "locations": [location(&self.primary)],
+            "relatedLocations": related,
+            "fixes": fixes,
+        })
+    }
+}
+
+/// A SARIF 2.1.0 log of the results returned by [`Diagnostic::to_sarif`].
+#[cfg(feature = "cli")]
+pub fn sarif_log(results: Vec<Value>) -> Value {
+    let rules: Vec<_> = Code::ALL
+        .iter()
+        .filter(|code| results.iter().any(|result| result["ruleId"] == code.id()))
+        .map(|code| {
+            json!({
+                "id": code.id(),
+                "name": code.name(),
+                "help": { "text": format!("run `ssl --explain {code}` for details") },
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": { "driver": { "name": "ssl", "rules": rules } },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+/// The one-based line and column of a byte offset, counting columns in characters.
+#[cfg(feature = "cli")]
+fn line_col(index: &LineIndex, offset: TextSize) -> (usize, usize) {
+    let line_col = index.to_wide(WideEncoding::Utf32, index.line_col(offset));
+    (line_col.line as usize + 1, line_col.col as usize + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn json() {
+        let source = "D1 = 0\nD1 = 3\n";
+        let span = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let diagnostic = Diagnostic::warning(
+            Code::ShadowedAssignment,
+            "overwritten",
+            Label::new(span(7, 9), "here"),
+        )
+        .with_label(Label::new(span(0, 2), "previous"));
+        let json = diagnostic.to_json("a.ssl", source);
+        assert_eq!(json["severity"], "warning");
+        assert_eq!(json["spans"][0]["line_start"], 2);
+        assert_eq!(json["spans"][0]["column_start"], 1);
+        assert_eq!(json["spans"][0]["column_end"], 3);
+        assert_eq!(json["spans"][1]["is_primary"], false);
+        assert_eq!(json["spans"][1]["byte_end"], 2);
+
+        let log = sarif_log(vec![diagnostic.to_sarif("a.ssl", source)]);
+        let result = &log["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "SSL0005");
+        assert_eq!(
+            log["runs"][0]["tool"]["driver"]["rules"][0]["name"],
+            "shadowed-assignment"
+        );
+        assert_eq!(
+            result["locations"][0]["physicalLocation"]["region"]["startLine"],
+            2
+        );
+        assert_eq!(result["relatedLocations"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn render() {
+        let source = "D1 = 0\nD1 = 3\n";
+        let span = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let diagnostic =
+            Diagnostic::error(Code::SyntaxError, "bad", Label::new(span(7, 9), "here"))
+                .with_label(Label::new(span(0, 2), "previous"))
+                .with_note("a note")
+                .with_suggestion("try", span(7, 9), "E1");
+        let expected = "\
+error[SSL0001]: bad
+ --> a.ssl:2:1
+  |
+1 | D1 = 0
+  | -- previous
+2 | D1 = 3
+  | ^^ here
+  |
+  = note: a note
+  = help: try: `E1`";
+        assert_eq!(diagnostic.render("a.ssl", source, false), expected);
+    }
+
+    /// The first example of every explanation must produce its code, and the last
+    /// one must be free of errors.
+    #[test]
+    fn explanations() {
+        for &code in Code::ALL {
+            assert_eq!(Code::from_id(&code.id().to_lowercase()), Some(code));
+            let examples: Vec<_> = code
+                .explanation()
+                .split("```ssl\n")
+                .skip(1)
+                .map(|block| &block[..block.find("```").unwrap()])
+                .collect();
+            assert!(examples.len() >= 2, "{code}");
+            assert!(diagnostics(examples[0]).contains(&code), "{code}");
+            let last = diagnostics(examples.last().unwrap());
+            assert!(
+                last.iter().all(|c| *c == Code::ShadowedAssignment),
+                "{code}"
+            );
+        }
+    }
+
+    /// The codes of every diagnostic reported for `source` with the default lint
+    /// levels, including by evaluation.
+    fn diagnostics(source: &str) -> Vec<Code> {
+        let parse = crate::parse(source);
+        let mut codes: Vec<_> = parse.errors.iter().map(|d| d.code).collect();
+        let sheet = crate::analyze(&parse);
+        codes.extend(
+            sheet
+                .issues
+                .iter()
+                .filter(|issue| issue.is_error())
+                .map(|issue| issue.diagnostic(source).code),
+        );
+        let config = crate::lint::Config::default();
+        codes.extend(
+            crate::lint::lint(&parse, &sheet, source, &config)
+                .iter()
+                .map(|d| d.code),
+        );
+        let mut evaluator = crate::eval::Evaluator::new(&sheet);
+        for (cell, assignment) in sheet.cells() {
+            if let Err(err) = evaluator.eval(cell) {
+                codes.push(err.diagnostic(assignment.span).code);
+            }
+        }
+        codes
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn columns_count_chars() {
+        let at = |source, offset: u32| line_col(&LineIndex::new(source), offset.into());
+        assert_eq!(at("é = 1\n", 2), (1, 2));
+        assert_eq!(at("a\nb", 3), (2, 2));
+        assert_eq!(at("a\r\nb", 4), (2, 2));
+    }
+}
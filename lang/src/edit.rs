@@ -0,0 +1,922 @@
+//! Changing a sheet by editing its source, for the app.
+//!
+//! Each change is a minimal [`TextEdit`] of the source of a [`Parse`]: a value is
+//! replaced where it is written, a range is split where it is written when one
+//! of its cells changes, and new statements get a line of their own. So
+//! comments, formatting and the order of statements are kept as they are, and a
+//! file saved by the app only differs where something was changed.
+//!
+//! Inserting or deleting rows and columns rewrites every reference that moves,
+//! like [`insert_rows`] and [`delete_rows`] describe.
+//!
+//! The edits of a call are relative to the source it was given.
+//! [`TextEdit::apply`] applies them to the source, and [`Parse::reparse`] can
+//! apply them one at a time, last first, to the parse.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use cstree::text::{TextRange, TextSize};
+use cstree::util::NodeOrToken;
+
+use crate::cell_index::{CellRef, Col, RangeRef, Row};
+use crate::eval;
+use crate::ide::is_alias_name;
+use crate::parser::{Parse, SyntaxKind::*, SyntaxNode, SyntaxToken, TextEdit};
+use crate::sema::{self, Assignment, Sheet, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    /// The value can't be written in the language, like a string containing `"`.
+    InvalidValue(Value),
+    /// The name isn't a valid alias name.
+    InvalidName(String),
+    /// An alias of this name is already defined at `span`.
+    Taken { name: String, span: TextRange },
+    /// The cell is assigned as part of the range at this span, which can't be
+    /// removed without emptying the other cells too.
+    InRange(TextRange),
+    /// The text isn't a single statement without syntax errors.
+    InvalidStatement(String),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::InvalidValue(value) => write!(f, "{value:?} can't be written in a sheet"),
+            EditError::InvalidName(name) => write!(f, "`{name}` is not a valid alias name"),
+            EditError::Taken { name, .. } => write!(f, "`${name}` is already defined"),
+            EditError::InRange(_) => f.write_str("the cell is assigned as part of a range"),
+            EditError::InvalidStatement(text) => write!(f, "`{text}` is not a statement"),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// Give `cell` the value `value`.
+///
+/// If the assignment that gives the cell its value assigns only that cell, its
+/// value is replaced. If it assigns a range, it is split into assignments of the
+/// rest of the range, around one of `cell`. A string for a cell holding an enum
+/// picks one of its options, right after the enum, like [`crate::sema`]
+/// describes, so the enum stays. Otherwise, an assignment is added at the end of
+/// the file, where it overrides every earlier one.
+pub fn set_cell(
+    parse: &Parse,
+    sheet: &Sheet,
+    cell: CellRef,
+    value: &Value,
+) -> Result<TextEdit, EditError> {
+    let replacement = write_value(value)?;
+    let source = parse.source();
+    let root = parse.red_tree();
+    let line = format!("{cell} = {replacement}");
+    let Some((assignment, assign)) = sheet
+        .get(cell)
+        .and_then(|assignment| Some((assignment, assign(&root, assignment)?)))
+    else {
+        return Ok(insert_line(source, source.len(), &line));
+    };
+    if let (Value::Enum(_), Value::Str(_)) = (&assignment.value, value) {
+        let end = assign.parent().map_or(source.len(), |statement| {
+            usize::from(statement.text_range().end())
+        });
+        return Ok(insert_line(source, end, &line));
+    }
+    let edit = if assignment.target == RangeRef::from(cell) {
+        assign.children().nth(1).map(|expr| TextEdit {
+            span: sema::trimmed_range(expr),
+            replacement,
+        })
+    } else {
+        split_range(source, &assign, assignment, cell, &line)
+    };
+    Ok(edit.unwrap_or_else(|| insert_line(source, source.len(), &line)))
+}
+
+/// Replace `assign`, which assigns a range including `cell`, by assignments of
+/// the rest of the range with the same values, and `line` for `cell`. `None` if
+/// the range is assigned from a range of another size, whose parts could end up
+/// with the size of the source.
+fn split_range(
+    source: &str,
+    assign: &SyntaxNode,
+    assignment: &Assignment,
+    cell: CellRef,
+    line: &str,
+) -> Option<TextEdit> {
+    let target = assignment.target;
+    let expr = sema::trimmed_range(assign.children().nth(1)?);
+    let expr = &source[Range::<usize>::from(expr)];
+    // A range read cell by cell is split along with the target.
+    let value = |part: RangeRef| match assignment.value {
+        Value::Ref(from) if from.start() != from.end() => {
+            let start = eval::source_cell(target, from, part.start())?;
+            let end = eval::source_cell(target, from, part.end())?;
+            Some(write_place(RangeRef::new(start, end)))
+        }
+        _ => Some(expr.to_owned()),
+    };
+
+    let (start_col, start_row) = target.start().indices();
+    let (end_col, end_row) = target.end().indices();
+    let (col, row) = cell.indices();
+    let part = |cols: (u128, u128), rows: (u128, u128)| {
+        let start = CellRef::from_indices(cols.0, rows.0)?;
+        let end = CellRef::from_indices(cols.1, rows.1)?;
+        let part = RangeRef::new(start, end);
+        Some(format!("{} = {}", write_place(part), value(part)?))
+    };
+    // The rows above, the cells left and right of `cell`, and the rows below.
+    let lines = [
+        (row > start_row).then(|| part((start_col, end_col), (start_row, row - 1))),
+        (col > start_col).then(|| part((start_col, col - 1), (row, row))),
+        Some(Some(line.to_owned())),
+        (col < end_col).then(|| part((col + 1, end_col), (row, row))),
+        (row < end_row).then(|| part((start_col, end_col), (row + 1, end_row))),
+    ];
+    let lines: Vec<_> = lines.into_iter().flatten().collect::<Option<_>>()?;
+    Some(replace_with_lines(source, assign, &lines))
+}
+
+/// Replace `node` by `lines`, each with the indentation of its line.
+fn replace_with_lines(source: &str, node: &SyntaxNode, lines: &[String]) -> TextEdit {
+    let span = sema::trimmed_range(node);
+    let start = usize::from(span.start());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let indent = &source[line_start..start];
+    let indent = if indent.trim().is_empty() { indent } else { "" };
+    let replacement = lines.join(&format!("\n{indent}"));
+    TextEdit { span, replacement }
+}
+
+/// Empty `cell`, by removing every statement that assigns it, in source order.
+/// Comments above them are kept.
+pub fn remove_cell(
+    parse: &Parse,
+    sheet: &Sheet,
+    cell: CellRef,
+) -> Result<Vec<TextEdit>, EditError> {
+    let root = parse.red_tree();
+    let mut edits = Vec::new();
+    for assignment in sheet.history(cell) {
+        if assignment.target != RangeRef::from(cell) {
+            return Err(EditError::InRange(assignment.span));
+        }
+        let Some(statement) = assign(&root, assignment).and_then(|assign| assign.parent().cloned())
+        else {
+            continue;
+        };
+        edits.push(TextEdit {
+            span: statement.text_range(),
+            replacement: String::new(),
+        });
+    }
+    Ok(edits)
+}
+
+/// Define `$name` as an alias for `target`, after the last `alias` statement, or
+/// before the first statement if there is none.
+pub fn add_alias(
+    parse: &Parse,
+    sheet: &Sheet,
+    name: &str,
+    target: RangeRef,
+) -> Result<TextEdit, EditError> {
+    if !is_alias_name(name) {
+        return Err(EditError::InvalidName(name.to_owned()));
+    }
+    if let Some(alias) = sheet.alias(name) {
+        return Err(EditError::Taken {
+            name: name.to_owned(),
+            span: alias.span,
+        });
+    }
+    let source = parse.source();
+    let root = parse.red_tree();
+    let statements: Vec<_> = root
+        .children()
+        .filter(|node| node.kind() == STATEMENT)
+        .collect();
+    let offset = match statements.iter().rev().find(|statement| {
+        statement
+            .first_child()
+            .is_some_and(|inner| inner.kind() == ALIAS_STMT)
+    }) {
+        Some(alias) => alias.text_range().end(),
+        None => statements
+            .first()
+            .map_or(TextSize::of(source), |statement| {
+                statement.text_range().start()
+            }),
+    };
+    let line = format!("alias {name} = {}", write_place(target));
+    Ok(insert_line(source, usize::from(offset), &line))
+}
+
+/// Insert `statement` on a line of its own, after the line at `offset`, with the
+/// same indentation as that line.
+pub fn insert_statement_after(
+    parse: &Parse,
+    offset: TextSize,
+    statement: &str,
+) -> Result<TextEdit, EditError> {
+    let invalid = || EditError::InvalidStatement(statement.to_owned());
+    let parsed = crate::parse(statement);
+    let root = parsed.red_tree();
+    let mut children = root.children_with_tokens();
+    match (children.next(), children.next()) {
+        (Some(NodeOrToken::Node(node)), None)
+            if parsed.errors.is_empty()
+                && node.kind() == STATEMENT
+                && !statement.ends_with('\n') => {}
+        _ => return Err(invalid()),
+    }
+
+    let source = parse.source();
+    let root = parse.red_tree();
+    let children: Vec<TextRange> = root
+        .children_with_tokens()
+        .map(|elem| match elem {
+            NodeOrToken::Node(node) => node.text_range(),
+            NodeOrToken::Token(token) => token.text_range(),
+        })
+        .collect();
+    let Some(first) = children
+        .iter()
+        .position(|child| child.contains(offset))
+        .or(children.len().checked_sub(1))
+    else {
+        return Ok(insert_line(source, 0, statement));
+    };
+    let start = usize::from(children[first].start());
+    // A blank line can be made of whitespace and a newline, so look for the child
+    // that ends the line.
+    let end = children[first..]
+        .iter()
+        .map(|child| usize::from(child.end()))
+        .find(|&end| source[..end].ends_with('\n'))
+        .unwrap_or(source.len());
+    let line = &source[start..end];
+    let indent = match line.trim() {
+        "" => "",
+        _ => &line[..line.len() - line.trim_start_matches([' ', '\t']).len()],
+    };
+    Ok(insert_line(source, end, &format!("{indent}{statement}")))
+}
+
+/// Insert `count` empty rows above `at`. Like in other spreadsheets, every
+/// reference to a cell at or below `at` moves down with it, and a range that
+/// spans `at` grows. A range assigned from a range that doesn't grow at the same
+/// place is split where either of them does, into assignments that read the
+/// same cells as before.
+pub fn insert_rows(parse: &Parse, sheet: &Sheet, at: Row, count: u128) -> Vec<TextEdit> {
+    let shift = Shift::Insert {
+        at: at.index(),
+        count,
+    };
+    move_cells(parse, sheet, Axis::Rows, shift)
+}
+
+/// Delete `count` rows, starting with `start`. References below them move up,
+/// and ranges that span them shrink.
+///
+/// Assignments to deleted cells only are removed. A reference whose cells are all
+/// deleted can't keep its meaning, so like in other spreadsheets it becomes
+/// `#REF!`, which refers to nothing, and the `deleted-reference` lint reports it
+/// until it is fixed. So does the source of a range assigned from a range that
+/// loses other cells than it, since its cells would read other cells than before.
+pub fn delete_rows(parse: &Parse, sheet: &Sheet, start: Row, count: u128) -> Vec<TextEdit> {
+    let shift = Shift::Delete {
+        start: start.index(),
+        count,
+    };
+    move_cells(parse, sheet, Axis::Rows, shift)
+}
+
+/// Insert `count` empty columns left of `at`, like [`insert_rows`].
+pub fn insert_cols(parse: &Parse, sheet: &Sheet, at: Col, count: u128) -> Vec<TextEdit> {
+    let shift = Shift::Insert {
+        at: at.index(),
+        count,
+    };
+    move_cells(parse, sheet, Axis::Cols, shift)
+}
+
+/// Delete `count` columns, starting with `start`, like [`delete_rows`].
+pub fn delete_cols(parse: &Parse, sheet: &Sheet, start: Col, count: u128) -> Vec<TextEdit> {
+    let shift = Shift::Delete {
+        start: start.index(),
+        count,
+    };
+    move_cells(parse, sheet, Axis::Cols, shift)
+}
+
+/// Insert `line` at `offset`, which is the start of a line or the end of the file.
+fn insert_line(source: &str, offset: usize, line: &str) -> TextEdit {
+    // keep a file that doesn't end with a newline that way
+    let replacement = if offset == source.len() && !source.is_empty() && !source.ends_with('\n') {
+        format!("\n{line}")
+    } else {
+        format!("{line}\n")
+    };
+    TextEdit {
+        span: TextRange::empty(TextSize::from(offset as u32)),
+        replacement,
+    }
+}
+
+/// The name in an `alias` statement or an `$alias` expression.
+fn ident(node: &SyntaxNode) -> Option<&SyntaxToken> {
+    node.children_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .find(|token| token.kind() == IDENT)
+}
+
+/// The `ASSIGN` node of `assignment`.
+fn assign(root: &SyntaxNode, assignment: &Assignment) -> Option<SyntaxNode> {
+    root.descendants()
+        .find(|node| {
+            node.kind() == ASSIGN
+                && node
+                    .first_child()
+                    .is_some_and(|place| sema::trimmed_range(place) == assignment.span)
+        })
+        .cloned()
+}
+
+fn write_value(value: &Value) -> Result<String, EditError> {
+    Ok(match value {
+        Value::Int(n) => n.to_string(),
+        // there are no string escapes
+        Value::Str(s) if s.contains('"') => return Err(EditError::InvalidValue(value.clone())),
+        Value::Str(s) => format!("\"{s}\""),
+        Value::Enum(range) => format!("enum {}", write_place(*range)),
+        Value::Ref(range) => write_place(*range),
+    })
+}
+
+/// A range as it would be written by hand: `A1` for a single cell.
+pub(crate) fn write_place(range: RangeRef) -> String {
+    if range.start() == range.end() {
+        range.start().to_string()
+    } else {
+        range.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Axis {
+    Rows,
+    Cols,
+}
+
+impl Axis {
+    fn index(self, cell: CellRef) -> u128 {
+        match self {
+            Axis::Rows => cell.row.index(),
+            Axis::Cols => cell.col.index(),
+        }
+    }
+
+    fn with_index(self, cell: CellRef, index: u128) -> Option<CellRef> {
+        match self {
+            Axis::Rows => Some(CellRef::new(cell.col, Row::from_index(index)?)),
+            Axis::Cols => Some(CellRef::new(Col::from_index(index), cell.row)),
+        }
+    }
+}
+
+/// Rows or columns being inserted or deleted, as zero-based indices along their
+/// axis.
+#[derive(Debug, Clone, Copy)]
+enum Shift {
+    Insert { at: u128, count: u128 },
+    Delete { start: u128, count: u128 },
+}
+
+impl Shift {
+    /// Where the cell at `index` moves, or `None` if it is deleted or pushed off
+    /// the sheet.
+    fn cell(self, index: u128) -> Option<u128> {
+        match self {
+            Shift::Insert { at, count } if index >= at => index.checked_add(count),
+            Shift::Delete { start, count } if index >= start => {
+                if index - start < count {
+                    None
+                } else {
+                    Some(index - count)
+                }
+            }
+            _ => Some(index),
+        }
+    }
+
+    /// Where the cells from `lo` to `hi` move. A range that loses some of its cells
+    /// shrinks to the others; `None` if it loses all of them.
+    fn range(self, lo: u128, hi: u128) -> Option<(u128, u128)> {
+        match self {
+            Shift::Insert { .. } => Some((self.cell(lo)?, self.cell(hi)?)),
+            Shift::Delete { start, .. } => {
+                let lo = self.cell(lo).unwrap_or(start);
+                let hi = match self.cell(hi) {
+                    Some(hi) => hi,
+                    None => start.checked_sub(1)?,
+                };
+                (lo <= hi).then_some((lo, hi))
+            }
+        }
+    }
+
+    /// The cells from `lo` to `hi` that are deleted, or inserted among them, as
+    /// an offset from `lo` and a count. `None` if the range only moves.
+    fn change(self, lo: u128, hi: u128) -> Option<(u128, u128)> {
+        match self {
+            Shift::Insert { at, count } => {
+                (count > 0 && lo < at && at <= hi).then_some((at - lo, count))
+            }
+            Shift::Delete { start, count } => {
+                let first = lo.max(start);
+                let last = hi.min(start.saturating_add(count).checked_sub(1)?);
+                (first <= last).then_some((first - lo, last - first + 1))
+            }
+        }
+    }
+}
+
+fn move_cells(parse: &Parse, sheet: &Sheet, axis: Axis, shift: Shift) -> Vec<TextEdit> {
+    let root = parse.red_tree();
+    let mut edits = Vec::new();
+    // indices into `sheet.definitions` of the aliases of deleted cells
+    let mut deleted_aliases = HashSet::new();
+    for statement in root.children().filter(|node| node.kind() == STATEMENT) {
+        let Some(inner) = statement.first_child() else {
+            continue;
+        };
+        let assignment = inner
+            .first_child()
+            .filter(|_| inner.kind() == ASSIGN)
+            .map(sema::trimmed_range)
+            .and_then(|span| {
+                sheet
+                    .assignments
+                    .iter()
+                    .find(|assignment| assignment.span == span)
+            });
+        // The source of a range assigned from a range of the same size, if cells
+        // are deleted or inserted at different places of the two. The cells of
+        // the target would then read other cells than they did, so it can't be
+        // kept, unless the assignment can be split around the inserted cells.
+        let mut misaligned = None;
+        if let Some(assignment) = assignment {
+            let target = assignment.target;
+            let (lo, hi) = (axis.index(target.start()), axis.index(target.end()));
+            // an assignment goes away with the cells it assigns
+            if shift.range(lo, hi).is_none() {
+                edits.push(TextEdit {
+                    span: statement.text_range(),
+                    replacement: String::new(),
+                });
+                continue;
+            }
+            if let Value::Ref(source) = assignment.value {
+                let aligned = source.start() == source.end()
+                    || eval::source_cell(target, source, target.end()).is_none()
+                    || shift.change(lo, hi)
+                        == shift.change(axis.index(source.start()), axis.index(source.end()));
+                let split = match shift {
+                    Shift::Insert { .. } if !aligned => {
+                        split_misaligned(axis, shift, target, source)
+                    }
+                    _ => None,
+                };
+                if let Some(lines) = split {
+                    edits.push(replace_with_lines(parse.source(), inner, &lines));
+                    continue;
+                }
+                if !aligned {
+                    misaligned = inner.children().nth(1).map(sema::trimmed_range);
+                }
+            }
+        }
+
+        // The new text of each cell that moves, in source order. `None` for a
+        // reference whose cells were all deleted.
+        let mut moved: Vec<(TextRange, Option<String>)> = Vec::new();
+        for elem in statement.descendants_with_tokens() {
+            let (span, cells): (_, Vec<_>) = match elem {
+                NodeOrToken::Node(range) if range.kind() == CELL_RANGE => (
+                    sema::trimmed_range(range),
+                    range
+                        .children_with_tokens()
+                        .filter_map(|elem| elem.into_token())
+                        .filter(|token| token.kind() == CELL)
+                        .collect(),
+                ),
+                NodeOrToken::Token(token)
+                    if token.kind() == CELL && token.parent().kind() != CELL_RANGE =>
+                {
+                    (token.text_range(), vec![token])
+                }
+                NodeOrToken::Node(alias) if alias.kind() == ALIAS_EXPR => {
+                    let deleted = ident(alias)
+                        .and_then(|name| {
+                            sheet
+                                .references
+                                .iter()
+                                .find(|reference| reference.span == name.text_range())
+                        })
+                        .and_then(|reference| reference.definition)
+                        .is_some_and(|i| deleted_aliases.contains(&i));
+                    if deleted {
+                        moved.push((sema::trimmed_range(alias), None));
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            match move_reference(parse, axis, shift, &cells) {
+                Some(cells) => {
+                    moved.extend(cells.into_iter().map(|(span, text)| (span, Some(text))))
+                }
+                None => moved.push((span, None)),
+            }
+        }
+        if let Some(source) = misaligned {
+            moved.retain(|(span, _)| !source.contains_range(*span));
+            let at = moved.partition_point(|(span, _)| span.start() < source.start());
+            moved.insert(at, (source, None));
+        }
+        // the uses of an alias that now refers to nothing become `#REF!` too
+        if moved.iter().any(|(_, text)| text.is_none()) {
+            let definition = ident(inner).and_then(|name| {
+                sheet
+                    .definitions
+                    .iter()
+                    .position(|alias| alias.span == name.text_range())
+            });
+            deleted_aliases.extend(definition.filter(|_| inner.kind() == ALIAS_STMT));
+        }
+        edits.extend(moved.into_iter().map(|(span, text)| TextEdit {
+            span,
+            replacement: text.unwrap_or_else(|| "#REF!".to_owned()),
+        }));
+    }
+    edits
+}
+
+/// The assignments that `target = source` is split into when cells are inserted
+/// at different places of the two, one for each part of `target` that still
+/// reads the cells it read before. `None` if some of them would be pushed off the
+/// sheet.
+fn split_misaligned(
+    axis: Axis,
+    shift: Shift,
+    target: RangeRef,
+    source: RangeRef,
+) -> Option<Vec<String>> {
+    let (lo, hi) = (axis.index(target.start()), axis.index(target.end()));
+    let source_lo = axis.index(source.start());
+    // where each part starts, as an offset from the start of both ranges
+    let mut cuts: Vec<u128> = [
+        shift.change(lo, hi),
+        shift.change(source_lo, axis.index(source.end())),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|(offset, _)| offset)
+    .collect();
+    cuts.sort_unstable();
+    cuts.dedup();
+    let starts = std::iter::once(0).chain(cuts.iter().copied());
+    let ends = cuts.iter().map(|&cut| cut - 1).chain([hi - lo]);
+    // the cells of a part don't straddle the inserted ones, so they move together
+    let part = |range: RangeRef, lo: u128, first: u128, last: u128| {
+        let start = axis.with_index(range.start(), shift.cell(lo + first)?)?;
+        let end = axis.with_index(range.end(), shift.cell(lo + last)?)?;
+        Some(write_place(RangeRef::new(start, end)))
+    };
+    starts
+        .zip(ends)
+        .map(|(first, last)| {
+            Some(format!(
+                "{} = {}",
+                part(target, lo, first, last)?,
+                part(source, source_lo, first, last)?
+            ))
+        })
+        .collect()
+}
+
+/// The new text of the cells of a reference that move, or `None` if the
+/// reference loses all its cells. `cells` are the `CELL` tokens of a cell or a
+/// range.
+fn move_reference(
+    parse: &Parse,
+    axis: Axis,
+    shift: Shift,
+    cells: &[&SyntaxToken],
+) -> Option<Vec<(TextRange, String)>> {
+    let parsed: Result<Vec<CellRef>, _> = cells
+        .iter()
+        .map(|token| parse.text(token).parse())
+        .collect();
+    // an invalid cell like `A0` refers to nothing, so it stays as it is
+    let Ok(parsed) = parsed else {
+        return Some(Vec::new());
+    };
+    let indices: Vec<u128> = parsed.iter().map(|&cell| axis.index(cell)).collect();
+    let (lo, hi) = (*indices.iter().min()?, *indices.iter().max()?);
+    let (new_lo, new_hi) = shift.range(lo, hi)?;
+    let mut moved = Vec::new();
+    for ((token, cell), index) in cells.iter().zip(parsed).zip(indices) {
+        // each end of a range stays on its side, even if it was written backwards
+        let index = if index == lo { new_lo } else { new_hi };
+        let text = axis.with_index(cell, index)?.to_string();
+        if text != parse.text(token) {
+            moved.push((token.text_range(), text));
+        }
+    }
+    Some(moved)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cell(name: &str) -> CellRef {
+        name.parse().unwrap()
+    }
+
+    /// Apply `edit` to `source`, checking that the result still parses.
+    fn edited(source: &str, edit: impl FnOnce(&Parse, &Sheet) -> Vec<TextEdit>) -> String {
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let edits = edit(&parse, &sheet);
+        let output = TextEdit::apply(source, &edits).unwrap();
+        assert_eq!(crate::parse(&output).errors, [], "{output:?}");
+        let mut reparsed = crate::parse(source);
+        for edit in edits.iter().rev() {
+            reparsed.reparse(edit).unwrap();
+        }
+        assert_eq!(reparsed.source(), output);
+        output
+    }
+
+    #[test]
+    fn set() {
+        let source = "// fruits\nA1   = \"apple\"\nA2 = \"banana\"\nC1:C2 = 0\n";
+        let set = |name, value: Value| {
+            edited(source, |parse, sheet| {
+                vec![set_cell(parse, sheet, cell(name), &value).unwrap()]
+            })
+        };
+        // replaced where it is written
+        assert_eq!(
+            set("A1", Value::Int(42)),
+            "// fruits\nA1   = 42\nA2 = \"banana\"\nC1:C2 = 0\n"
+        );
+        // assigned as part of a range, which is split around the cell
+        assert_eq!(
+            set("C2", Value::Str("x".into())),
+            "// fruits\nA1   = \"apple\"\nA2 = \"banana\"\nC1 = 0\nC2 = \"x\"\n"
+        );
+        // or not at all
+        assert_eq!(
+            set("D1", Value::Str("x".into())),
+            format!("{source}D1 = \"x\"\n")
+        );
+        assert_eq!(
+            set("D1", Value::Enum(RangeRef::new(cell("A1"), cell("A2")))),
+            format!("{source}D1 = enum A1:A2\n")
+        );
+        assert_eq!(
+            edited("A1 = 1", |parse, sheet| {
+                vec![set_cell(parse, sheet, cell("A2"), &Value::Ref(cell("A1").into())).unwrap()]
+            }),
+            "A1 = 1\nA2 = A1"
+        );
+
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let quote = Value::Str("\"".into());
+        assert_eq!(
+            set_cell(&parse, &sheet, cell("A1"), &quote),
+            Err(EditError::InvalidValue(quote))
+        );
+    }
+
+    #[test]
+    fn set_in_range() {
+        let set = |source, name| {
+            let output = edited(source, |parse, sheet| {
+                vec![set_cell(parse, sheet, cell(name), &Value::Int(9)).unwrap()]
+            });
+            // nothing the split range assigns is overwritten
+            let sheet = crate::analyze(&crate::parse(&output));
+            assert_eq!(sheet.issues, [], "{output:?}");
+            output
+        };
+        assert_eq!(
+            set("  B2:D4 = 1\n", "C3"),
+            "  B2:D2 = 1\n  B3 = 1\n  C3 = 9\n  D3 = 1\n  B4:D4 = 1\n"
+        );
+        assert_eq!(set("A1:A3 = 1\n", "A1"), "A1 = 9\nA2:A3 = 1\n");
+        // a range read cell by cell is split along with the target
+        assert_eq!(
+            set("A1:A3 = 1\nB1:B3 = A1:A3\n", "B3"),
+            "A1:A3 = 1\nB1:B2 = A1:A2\nB3 = 9\n"
+        );
+        assert_eq!(
+            set("A1 = 1\nB1:B3 = A1\n", "B2"),
+            "A1 = 1\nB1 = A1\nB2 = 9\nB3 = A1\n"
+        );
+    }
+
+    #[test]
+    fn set_enum() {
+        let source = "A1 = \"apple\"\nA2 = \"banana\"\nB1:B2 = enum A1:A2\nC1 = B1\n";
+        let set = |source, value: Value| {
+            edited(source, |parse, sheet| {
+                vec![set_cell(parse, sheet, cell("B1"), &value).unwrap()]
+            })
+        };
+        // a string picks an option, and keeps the enum
+        let picked = set(source, Value::Str("apple".into()));
+        assert_eq!(
+            picked,
+            "A1 = \"apple\"\nA2 = \"banana\"\nB1:B2 = enum A1:A2\nB1 = \"apple\"\nC1 = B1\n"
+        );
+        assert_eq!(crate::analyze(&crate::parse(&picked)).issues, []);
+        // picking again replaces the pick
+        assert_eq!(
+            set(picked.as_str(), Value::Str("banana".into())),
+            picked.replace("B1 = \"apple\"", "B1 = \"banana\"")
+        );
+        // anything else replaces the enum
+        assert_eq!(
+            set(source, Value::Int(1)),
+            "A1 = \"apple\"\nA2 = \"banana\"\nB1 = 1\nB2 = enum A1:A2\nC1 = B1\n"
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let source = "A1 = 1\n// one\nA1 = 2\nB1 = A1\nC1:C2 = 0\n";
+        assert_eq!(
+            edited(source, |parse, sheet| remove_cell(parse, sheet, cell("A1"))
+                .unwrap()),
+            "// one\nB1 = A1\nC1:C2 = 0\n"
+        );
+        assert_eq!(
+            edited(source, |parse, sheet| remove_cell(parse, sheet, cell("D1"))
+                .unwrap()),
+            source
+        );
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        assert_eq!(
+            remove_cell(&parse, &sheet, cell("C1")),
+            Err(EditError::InRange(TextRange::new(29.into(), 34.into())))
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        let range = RangeRef::new(cell("A1"), cell("A3"));
+        // after the last alias
+        let source = "A1 = 1\nalias one = A1\nB1 = $one\n";
+        assert_eq!(
+            edited(source, |parse, sheet| {
+                vec![add_alias(parse, sheet, "column", range).unwrap()]
+            }),
+            "A1 = 1\nalias one = A1\nalias column = A1:A3\nB1 = $one\n"
+        );
+        // before the first statement, below the comments at the top
+        assert_eq!(
+            edited("// sheet\n\nA1 = 1\n", |parse, sheet| {
+                vec![add_alias(parse, sheet, "first", cell("A1").into()).unwrap()]
+            }),
+            "// sheet\n\nalias first = A1\nA1 = 1\n"
+        );
+
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        assert_eq!(
+            add_alias(&parse, &sheet, "one", range),
+            Err(EditError::Taken {
+                name: "one".into(),
+                span: TextRange::new(13.into(), 16.into()),
+            })
+        );
+        assert_eq!(
+            add_alias(&parse, &sheet, "$two", range),
+            Err(EditError::InvalidName("$two".into()))
+        );
+    }
+
+    #[test]
+    fn rows() {
+        let source = "A5 = 1\nA3:A9 = 2\nalias col = A4:B6\nB1 = $col\nC4 = A5\n";
+        let row = |n: u128| Row::from_index(n - 1).unwrap();
+        assert_eq!(
+            edited(source, |parse, sheet| insert_rows(parse, sheet, row(5), 2)),
+            "A7 = 1\nA3:A11 = 2\nalias col = A4:B8\nB1 = $col\nC4 = A7\n"
+        );
+        // ranges shrink, and references to deleted cells become `#REF!`
+        assert_eq!(
+            edited(source, |parse, sheet| delete_rows(parse, sheet, row(5), 2)),
+            "A3:A7 = 2\nalias col = A4:B4\nB1 = $col\nC4 = #REF!\n"
+        );
+        // a range assigned from a range only shrinks if both lose the same cells
+        assert_eq!(
+            edited(
+                "B1:B3 = A5:A7\nC1:C3 = A1:A3\nD1:D3 = A1\n",
+                |parse, sheet| delete_rows(parse, sheet, row(2), 1)
+            ),
+            "B1:B2 = #REF!\nC1:C2 = A1:A2\nD1:D2 = A1\n"
+        );
+        // and is split where only one of them grows
+        let insert =
+            |source, at| edited(source, |parse, sheet| insert_rows(parse, sheet, row(at), 1));
+        assert_eq!(insert("B1:B3 = A5:A7\n", 6), "B1 = A5\nB2:B3 = A7:A8\n");
+        assert_eq!(
+            insert("  A5:B7 = C1:D3\n", 2),
+            "  A6:B6 = C1:D1\n  A7:B8 = C3:D4\n"
+        );
+        // the ends of a range written backwards stay where they are
+        assert_eq!(
+            edited("B1 = A9:A3\n", |parse, sheet| delete_rows(
+                parse,
+                sheet,
+                row(2),
+                3
+            )),
+            "B1 = A6:A2\n"
+        );
+    }
+
+    #[test]
+    fn cols() {
+        let source = "B1 = A1:C1\nalias x = B2\nD1 = $x\nE1 = D1\n";
+        let col = |name: &str| name.parse::<Col>().unwrap();
+        assert_eq!(
+            edited(source, |parse, sheet| insert_cols(
+                parse,
+                sheet,
+                col("B"),
+                1
+            )),
+            "C1 = A1:D1\nalias x = C2\nE1 = $x\nF1 = E1\n"
+        );
+        // an alias of deleted cells is deleted too
+        assert_eq!(
+            edited(source, |parse, sheet| delete_cols(
+                parse,
+                sheet,
+                col("B"),
+                1
+            )),
+            "alias x = #REF!\nC1 = #REF!\nD1 = C1\n"
+        );
+    }
+
+    #[test]
+    fn insert() {
+        let source = "A1 = 1\n  A2 = 2\n  \nA3 = 3";
+        let insert = |offset: u32, statement: &'static str| {
+            edited(source, move |parse, _| {
+                vec![insert_statement_after(parse, offset.into(), statement).unwrap()]
+            })
+        };
+        assert_eq!(insert(2, "B1 = 1"), "A1 = 1\nB1 = 1\n  A2 = 2\n  \nA3 = 3");
+        // with the indentation of the line
+        assert_eq!(
+            insert(7, "B1 = 1"),
+            "A1 = 1\n  A2 = 2\n  B1 = 1\n  \nA3 = 3"
+        );
+        // after a blank line of whitespace
+        assert_eq!(insert(16, "B1 = 1"), "A1 = 1\n  A2 = 2\n  \nB1 = 1\nA3 = 3");
+        // at the end of a file without a newline
+        assert_eq!(
+            insert(source.len() as u32, "alias x = B1"),
+            "A1 = 1\n  A2 = 2\n  \nA3 = 3\nalias x = B1"
+        );
+        assert_eq!(
+            edited("", |parse, _| {
+                vec![insert_statement_after(parse, 0.into(), "A1 = 1").unwrap()]
+            }),
+            "A1 = 1\n"
+        );
+
+        let parse = crate::parse(source);
+        for statement in ["A1 =", "A1 = 1\nA2 = 2", "// comment", "A1 = 1\n"] {
+            assert_eq!(
+                insert_statement_after(&parse, 0.into(), statement),
+                Err(EditError::InvalidStatement(statement.to_owned()))
+            );
+        }
+    }
+}
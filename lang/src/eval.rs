@@ -0,0 +1,294 @@
+//! Evaluation of cell values.
+//!
+//! References are live, like in any spreadsheet: `A2 = A1` holds whatever `A1`
+//! ends up holding, no matter where the statements are in the file.
+//!
+//! A range can be assigned from a range of the same size, in which case each cell
+//! reads the cell at the same position in the source (`B1:B3 = A1:A3` makes `B2`
+//! read `A2`), or from a single cell, which every cell of the target then reads.
+//!
+//! Evaluation doesn't recurse, so that a long chain of references can't overflow
+//! the stack, and an [`Evaluator`] evaluates each cell once.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use cstree::text::TextRange;
+
+use crate::cell_index::{CellRef, RangeRef};
+use crate::diagnostic::{Code, Diagnostic, Label};
+use crate::sema::{Sheet, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Evaluated {
+    Int(i64),
+    Str(String),
+    /// An enum cell, which can hold any of the given values.
+    Enum(Vec<Evaluated>),
+}
+
+impl fmt::Display for Evaluated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evaluated::Int(n) => write!(f, "{n}"),
+            Evaluated::Str(s) => write!(f, "\"{s}\""),
+            Evaluated::Enum(options) => {
+                f.write_str("enum(")?;
+                for (i, option) in options.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{option}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The value of this cell depends on itself.
+    Cycle(CellRef),
+    /// This cell was assigned as part of a range, from a range of a different size.
+    ShapeMismatch(CellRef),
+}
+
+impl EvalError {
+    /// Describe this error. `span` is the place of the assignment that failed to
+    /// evaluate.
+    pub fn diagnostic(&self, span: TextRange) -> Diagnostic {
+        let label = Label::new(span, "while evaluating this assignment");
+        match self {
+            EvalError::Cycle(cell) => Diagnostic::error(
+                Code::Cycle,
+                format!("the value of `{cell}` depends on itself"),
+                label,
+            ),
+            EvalError::ShapeMismatch(cell) => Diagnostic::error(
+                Code::ShapeMismatch,
+                "assigned from a range of a different size",
+                label,
+            )
+            .with_note(format!(
+                "`{cell}` has no corresponding cell in the source range"
+            )),
+        }
+    }
+}
+
+/// Evaluate a cell, or return `None` if nothing assigns it.
+///
+/// To evaluate many cells of the same sheet, use an [`Evaluator`], which
+/// remembers the value of every cell it evaluated.
+pub fn eval(sheet: &Sheet, cell: CellRef) -> Result<Option<Evaluated>, EvalError> {
+    Evaluator::new(sheet).eval(cell)
+}
+
+/// Evaluates the cells of a sheet, remembering their values, so that each cell
+/// is only evaluated once no matter how many others read it.
+pub struct Evaluator<'s> {
+    sheet: &'s Sheet,
+    /// The value of every cell that reads other cells and was evaluated so far.
+    values: HashMap<CellRef, Result<Option<Evaluated>, EvalError>>,
+    /// The cells currently being evaluated, innermost last. References can be
+    /// arbitrarily long chains, so this is a stack of its own rather than
+    /// recursion.
+    stack: Vec<Frame>,
+    /// The cells in `stack`, to find cycles.
+    in_progress: HashSet<CellRef>,
+}
+
+/// A cell whose value is read from other cells, waiting for their values.
+struct Frame {
+    cell: CellRef,
+    is_enum: bool,
+    /// The cells left to read.
+    reads: Box<dyn Iterator<Item = CellRef>>,
+    /// The values read so far, leaving out the cells that aren't assigned.
+    values: Vec<Evaluated>,
+}
+
+impl<'s> Evaluator<'s> {
+    pub fn new(sheet: &'s Sheet) -> Self {
+        Self {
+            sheet,
+            values: HashMap::new(),
+            stack: Vec::new(),
+            in_progress: HashSet::new(),
+        }
+    }
+
+    /// Evaluate a cell, or return `None` if nothing assigns it.
+    pub fn eval(&mut self, cell: CellRef) -> Result<Option<Evaluated>, EvalError> {
+        let mut returned = self.enter(cell);
+        while let Some(frame) = self.stack.last_mut() {
+            let result = match returned.take() {
+                Some(Err(err)) => Err(err),
+                received => {
+                    if let Some(Ok(Some(value))) = received {
+                        frame.values.push(value);
+                    }
+                    match frame.reads.next() {
+                        Some(read) => {
+                            returned = self.enter(read);
+                            continue;
+                        }
+                        None if frame.is_enum => {
+                            Ok(Some(Evaluated::Enum(std::mem::take(&mut frame.values))))
+                        }
+                        None => Ok(frame.values.pop()),
+                    }
+                }
+            };
+            let frame = self.stack.pop().unwrap();
+            self.in_progress.remove(&frame.cell);
+            // Every cell of a cycle depends on itself, while the cells that only
+            // read one get the error of the cell where they entered it.
+            let value = match &result {
+                Err(EvalError::Cycle(start))
+                    if *start == frame.cell || self.in_progress.contains(start) =>
+                {
+                    Err(EvalError::Cycle(frame.cell))
+                }
+                _ => result.clone(),
+            };
+            self.values.insert(frame.cell, value);
+            returned = Some(result);
+        }
+        // Either `cell` needed no frame, or its frame was the last one popped.
+        returned.unwrap()
+    }
+
+    /// Start evaluating `cell`. Returns its value if it doesn't read other cells,
+    /// or else pushes a frame to read them.
+    fn enter(&mut self, cell: CellRef) -> Option<Result<Option<Evaluated>, EvalError>> {
+        if let Some(value) = self.values.get(&cell) {
+            return Some(value.clone());
+        }
+        let Some(assignment) = self.sheet.get(cell) else {
+            return Some(Ok(None));
+        };
+        let (is_enum, reads): (_, Box<dyn Iterator<Item = CellRef>>) = match &assignment.value {
+            Value::Int(n) => return Some(Ok(Some(Evaluated::Int(*n)))),
+            Value::Str(s) => return Some(Ok(Some(Evaluated::Str(s.clone())))),
+            Value::Enum(range) => (true, Box::new(range.cells())),
+            Value::Ref(range) => match source_cell(assignment.target, *range, cell) {
+                Some(source) => (false, Box::new(std::iter::once(source))),
+                None => return Some(Err(EvalError::ShapeMismatch(cell))),
+            },
+        };
+        if !self.in_progress.insert(cell) {
+            return Some(Err(EvalError::Cycle(cell)));
+        }
+        self.stack.push(Frame {
+            cell,
+            is_enum,
+            reads,
+            values: Vec::new(),
+        });
+        None
+    }
+}
+
+/// The cell of `source` that `cell` reads, when `target` is assigned from `source`.
+pub(crate) fn source_cell(target: RangeRef, source: RangeRef, cell: CellRef) -> Option<CellRef> {
+    if source.start() == source.end() {
+        return Some(source.start());
+    }
+    let size = |range: RangeRef| {
+        let (start_col, start_row) = range.start().indices();
+        let (end_col, end_row) = range.end().indices();
+        (end_col - start_col, end_row - start_row)
+    };
+    if size(target) != size(source) {
+        return None;
+    }
+    let (target_col, target_row) = target.start().indices();
+    let (source_col, source_row) = source.start().indices();
+    let (col, row) = cell.indices();
+    CellRef::from_indices(source_col + col - target_col, source_row + row - target_row)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval_all(source: &str) -> Vec<(String, Result<Option<Evaluated>, EvalError>)> {
+        let sheet = crate::analyze(&crate::parse(source));
+        let mut evaluator = Evaluator::new(&sheet);
+        sheet
+            .cells()
+            .map(|(cell, _)| (cell.to_string(), evaluator.eval(cell)))
+            .collect()
+    }
+
+    #[test]
+    fn references() {
+        let values = eval_all("A1 = 3\nalias num = A1\nA2 = $num\nB1:B2 = A1:A2\nC1 = A9\n");
+        let values: Vec<_> = values
+            .iter()
+            .map(|(cell, value)| (cell.as_str(), value.clone().unwrap()))
+            .collect();
+        let three = Some(Evaluated::Int(3));
+        assert_eq!(
+            values,
+            [
+                ("A1", three.clone()),
+                ("A2", three.clone()),
+                ("B1", three.clone()),
+                ("B2", three),
+                ("C1", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn enums() {
+        let values = eval_all("A1 = \"apple\"\nA2 = \"banana\"\nB1 = enum A1:A3\n");
+        assert_eq!(
+            values[2].1.as_ref().unwrap().as_ref().unwrap().to_string(),
+            r#"enum("apple", "banana")"#
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let a1: CellRef = "A1".parse().unwrap();
+        let values = eval_all("A1 = B1\nB1 = A1\nC1:C2 = A1:A3\n");
+        assert_eq!(values[0].1, Err(EvalError::Cycle(a1)));
+        assert_eq!(values[1].1, Err(EvalError::Cycle("B1".parse().unwrap())));
+        assert_eq!(
+            values[2].1,
+            Err(EvalError::ShapeMismatch("C1".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn cycle_readers() {
+        // `C1` only reads the cycle, so it reports the cell where it entered it,
+        // whichever cell was evaluated first.
+        let a1: CellRef = "A1".parse().unwrap();
+        let values = eval_all("A1 = B1\nB1 = A1\nC1 = A1\n");
+        assert_eq!(values[2].1, Err(EvalError::Cycle(a1)));
+        let sheet = crate::analyze(&crate::parse("A1 = B1\nB1 = A1\nC1 = A1\n"));
+        let mut evaluator = Evaluator::new(&sheet);
+        assert_eq!(
+            evaluator.eval("C1".parse().unwrap()),
+            Err(EvalError::Cycle(a1))
+        );
+        assert_eq!(evaluator.eval(a1), Err(EvalError::Cycle(a1)));
+    }
+
+    #[test]
+    fn long_chain() {
+        let source: String = (2..=50_000)
+            .map(|row| format!("A{row} = A{}\n", row - 1))
+            .collect();
+        let sheet = crate::analyze(&crate::parse(&format!("A1 = 7\n{source}")));
+        assert_eq!(
+            eval(&sheet, "A50000".parse().unwrap()),
+            Ok(Some(Evaluated::Int(7)))
+        );
+    }
+}
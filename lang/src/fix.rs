@@ -0,0 +1,159 @@
+//! Applying the fixes that diagnostics suggest, for `ssl check --fix`.
+//!
+//! Only [machine-applicable](Applicability::MachineApplicable) suggestions are
+//! applied. Suggestions are edits of the source text, so two of them can't be
+//! applied together if they touch the same text; the later one is then left for
+//! another round, once the file was parsed again.
+
+use cstree::text::TextRange;
+
+use crate::diagnostic::{Applicability, Diagnostic, Suggestion};
+use crate::lint::{self, Config};
+use crate::parser::overlaps;
+use crate::{Parse, TextEdit};
+
+/// Give up after this many rounds, in case fixes keep suggesting each other.
+const MAX_ROUNDS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixed {
+    pub source: String,
+    /// How many suggestions were applied.
+    pub applied: usize,
+}
+
+/// Apply every machine-applicable fix suggested for `source`, including by the
+/// lints that `config` doesn't allow.
+///
+/// After each round of fixes the file is parsed again. If the round introduced
+/// syntax errors, it is thrown away and fixing stops there.
+pub fn fix(source: &str, config: &Config) -> Fixed {
+    let mut fixed = Fixed {
+        source: source.to_owned(),
+        applied: 0,
+    };
+    for _ in 0..MAX_ROUNDS {
+        let parse = crate::parse(&fixed.source);
+        let errors = parse.errors.len();
+        let diagnostics = diagnostics(&fixed.source, parse, config);
+        let suggestions = diagnostics
+            .iter()
+            .flat_map(|diagnostic| &diagnostic.suggestions)
+            .filter(|suggestion| suggestion.applicability == Applicability::MachineApplicable);
+        let edits = select(suggestions);
+        let Ok(source) = TextEdit::apply(&fixed.source, &edits) else {
+            break;
+        };
+        if edits.is_empty() || crate::parse(&source).errors.len() > errors {
+            break;
+        }
+        fixed.source = source;
+        fixed.applied += edits.len();
+    }
+    fixed
+}
+
+/// Every diagnostic `ssl check` would report: syntax errors, or if there are none
+/// the semantic errors and lints.
+fn diagnostics(source: &str, parse: Parse, config: &Config) -> Vec<Diagnostic> {
+    if !parse.errors.is_empty() {
+        return parse.errors;
+    }
+    let sheet = crate::analyze(&parse);
+    let mut diagnostics: Vec<_> = sheet
+        .issues
+        .iter()
+        .filter(|issue| issue.is_error())
+        .map(|issue| issue.diagnostic(source))
+        .collect();
+    diagnostics.extend(lint::lint(&parse, &sheet, source, config));
+    diagnostics
+}
+
+/// The edits of the suggestions that don't overlap an earlier one.
+fn select<'s>(suggestions: impl IntoIterator<Item = &'s Suggestion>) -> Vec<TextEdit> {
+    let mut suggestions: Vec<_> = suggestions.into_iter().collect();
+    suggestions.sort_by_key(|suggestion| (suggestion.span.start(), suggestion.span.end()));
+    suggestions.dedup_by(|a, b| a.span == b.span && a.replacement == b.replacement);
+
+    let mut edits: Vec<TextEdit> = Vec::new();
+    let mut last: Option<TextRange> = None;
+    for suggestion in suggestions {
+        if last.is_some_and(|last| overlaps(last, suggestion.span)) {
+            continue;
+        }
+        last = Some(suggestion.span);
+        edits.push(TextEdit {
+            span: suggestion.span,
+            replacement: suggestion.replacement.clone(),
+        });
+    }
+    edits
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn typos() {
+        let fixed = fix(
+            "alias fruits = A1:A3\nB1 = enum $friuts\nB2 = $fruit\n",
+            &Config::default(),
+        );
+        assert_eq!(
+            fixed.source,
+            "alias fruits = A1:A3\nB1 = enum $fruits\nB2 = $fruits\n"
+        );
+        assert_eq!(fixed.applied, 2);
+    }
+
+    #[test]
+    fn enum_member_case() {
+        let source = "A1 = \"apple\"\nA2 = \"Banana\"\nB1 = enum A1:A2\nB1 = \"APPLE\"\n\
+                      B2 = enum A1:A2\nB2 = \"banana\"\nB3 = \"Apple\"\n";
+        let fixed = fix(source, &Config::default());
+        assert_eq!(
+            fixed.source,
+            "A1 = \"apple\"\nA2 = \"Banana\"\nB1 = enum A1:A2\nB1 = \"apple\"\n\
+             B2 = enum A1:A2\nB2 = \"Banana\"\nB3 = \"Apple\"\n"
+        );
+        assert_eq!(fixed.applied, 2);
+
+        let mut config = Config::default();
+        config.set(lint::Lint::EnumMemberCase, lint::Level::Allow);
+        assert_eq!(fix(source, &config).applied, 0);
+    }
+
+    #[test]
+    fn nothing_to_fix() {
+        let source = "A1 = 1\nB1 = $nothing_like_it\n";
+        assert_eq!(
+            fix(source, &Config::default()),
+            Fixed {
+                source: source.to_owned(),
+                applied: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn overlapping() {
+        let span = |start: u32, end: u32| TextRange::new(start.into(), end.into());
+        let suggestion = |start, end, replacement: &str| Suggestion {
+            message: String::new(),
+            span: span(start, end),
+            replacement: replacement.to_owned(),
+            applicability: Applicability::MachineApplicable,
+        };
+        let suggestions = [
+            suggestion(5, 6, "2"),
+            suggestion(0, 2, "B1"),
+            suggestion(1, 3, "X"),
+            suggestion(6, 6, "0"),
+        ];
+        let edits = select(&suggestions);
+        assert_eq!(edits.len(), 2);
+        assert_eq!(TextEdit::apply("A1 = 1\n", &edits).unwrap(), "B1 = 2\n");
+    }
+}
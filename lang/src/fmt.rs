@@ -0,0 +1,190 @@
+//! A formatter working directly on the CST, so that it never loses comments.
+//!
+//! Formatting only ever changes whitespace:
+//! - tokens in a statement are separated by a single space, except around `:` and
+//!   after `$` (`B1 = enum A1:A3`, `A2 = $num`)
+//! - the `=` of consecutive assignments are aligned
+//! - runs of blank lines are collapsed into one, and blank lines at the start and
+//!   end of the file are removed
+//! - comments lose their indentation and trailing whitespace
+//! - lines end with `\n`, even if they ended with `\r\n`, and so does the file
+
+use cstree::util::NodeOrToken;
+
+use crate::parser::{Parse, SyntaxKind::*, SyntaxNode, SyntaxToken};
+
+pub(crate) enum Line {
+    Blank,
+    Comment(String),
+    Assign { place: String, expr: String },
+    Other(String),
+}
+
+/// Format a parsed file.
+///
+/// Returns `None` if the file has syntax errors, since the tree doesn't cover the
+/// whole file then.
+pub fn format(parse: &Parse) -> Option<String> {
+    if !parse.errors.is_empty() {
+        return None;
+    }
+    let root = parse.red_tree();
+    let mut lines = Vec::new();
+    for elem in root.children_with_tokens() {
+        match elem {
+            NodeOrToken::Node(stmt) => lines.push(statement(parse, stmt)),
+            // Statements and comments own their newline, so this is an empty line.
+            NodeOrToken::Token(token) if token.kind() == NEWLINE => lines.push(Line::Blank),
+            NodeOrToken::Token(token) if token.kind() == COMMENT => {
+                lines.push(comment(parse, token))
+            }
+            NodeOrToken::Token(_) => {}
+        }
+    }
+    Some(render(&lines))
+}
+
+pub(crate) fn comment(parse: &Parse, token: &SyntaxToken) -> Line {
+    Line::Comment(parse.text(token).trim().to_owned())
+}
+
+pub(crate) fn statement(parse: &Parse, stmt: &SyntaxNode) -> Line {
+    let Some(inner) = stmt.first_child() else {
+        return Line::Other(join(parse, stmt));
+    };
+    if inner.kind() == ASSIGN {
+        let mut children = inner.children();
+        if let (Some(place), Some(expr)) = (children.next(), children.next()) {
+            return Line::Assign {
+                place: join(parse, place),
+                expr: join(parse, expr),
+            };
+        }
+    }
+    Line::Other(join(parse, inner))
+}
+
+/// The text of `node` with all whitespace normalized.
+fn join(parse: &Parse, node: &SyntaxNode) -> String {
+    let mut out = String::new();
+    let mut prev = None;
+    for token in node
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+    {
+        let kind = token.kind();
+        if matches!(kind, WHITESPACE | NEWLINE) {
+            continue;
+        }
+        if prev.is_some_and(|prev| !matches!(prev, DOLLAR | COLON)) && kind != COLON {
+            out.push(' ');
+        }
+        out.push_str(parse.text(token));
+        prev = Some(kind);
+    }
+    out
+}
+
+pub(crate) fn render(lines: &[Line]) -> String {
+    // The width of the widest place in each run of assignments.
+    let mut widths = vec![0; lines.len()];
+    let mut start = 0;
+    for i in 0..=lines.len() {
+        if let Some(Line::Assign { .. }) = lines.get(i) {
+            continue;
+        }
+        let width = lines[start..i]
+            .iter()
+            .map(|line| match line {
+                Line::Assign { place, .. } => place.chars().count(),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        widths[start..i].fill(width);
+        start = i + 1;
+    }
+
+    let mut out = String::new();
+    let mut blank = false;
+    for (line, width) in lines.iter().zip(widths) {
+        let text = match line {
+            Line::Blank => {
+                blank = !out.is_empty();
+                continue;
+            }
+            Line::Comment(text) | Line::Other(text) => text.clone(),
+            Line::Assign { place, expr } => format!("{place:width$} = {expr}"),
+        };
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(&text);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::SyntaxKind;
+
+    #[track_caller]
+    fn check(input: &str, expected: &str) {
+        let formatted = format(&crate::parse(input)).unwrap();
+        assert_eq!(formatted, expected);
+    }
+
+    /// Every node, and every token that isn't whitespace, in order.
+    fn significant(parse: &Parse) -> Vec<(SyntaxKind, String)> {
+        parse
+            .red_tree()
+            .descendants_with_tokens()
+            .filter_map(|elem| match elem {
+                NodeOrToken::Node(node) => Some((node.kind(), String::new())),
+                NodeOrToken::Token(token) if matches!(token.kind(), WHITESPACE | NEWLINE) => None,
+                NodeOrToken::Token(token) => Some((token.kind(), parse.text(token).trim().into())),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalize() {
+        check(
+            "\n\nD1=0\nA10   =  \"x\"\n  // note  \n\n\n\nalias  num=A1\nB1 = enum  A1 : A3\nA2 =$num\n\n",
+            "D1  = 0\nA10 = \"x\"\n// note\n\nalias num = A1\nB1 = enum A1:A3\nA2 = $num\n",
+        );
+    }
+
+    #[test]
+    fn fixtures() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/raw_dumps");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("ssl".as_ref()) {
+                continue;
+            }
+            let input = std::fs::read_to_string(&path).unwrap();
+            let parse = crate::parse(&input);
+            let Some(formatted) = format(&parse) else {
+                continue;
+            };
+            let reparsed = crate::parse(&formatted);
+            assert!(reparsed.errors.is_empty(), "{}", path.display());
+            assert_eq!(
+                significant(&parse),
+                significant(&reparsed),
+                "{}",
+                path.display()
+            );
+            assert_eq!(
+                format(&reparsed).as_ref(),
+                Some(&formatted),
+                "{}",
+                path.display()
+            );
+        }
+    }
+}
@@ -23,7 +23,8 @@ fn ws<'a>() -> impl CSTParser<'a> {
     rowan_leaf(
         WHITESPACE,
         any::<_, CSTExtra<'a>>()
-            .filter(|c: &char| *c != '\n' && c.is_whitespace())
+            // `\r` is only allowed as part of a `\r\n` newline
+            .filter(|c: &char| !matches!(c, '\n' | '\r') && c.is_whitespace())
             .repeated()
             .at_least(1)
             .or_not(),
@@ -34,18 +35,29 @@ fn leaf<'a, O>(kind: SyntaxKind, parser: impl CSTParser<'a, O>) -> impl CSTParse
     ws().then_ignore(rowan_leaf(kind, parser))
 }
 
+// The labels are what syntax errors say we expected, so they are written for
+// users rather than for us.
 leafs! {
     fn eq: EQ = just('=');
-    fn nl: NEWLINE = just('\n');
-    fn int: INT = text::digits(10);
+    fn nl: NEWLINE = just("\r\n").or(just("\n")).labelled("end of line");
+    fn int: INT = text::digits(10).labelled("a number");
     fn colon: COLON = just(':');
-    fn dollar: DOLLAR = just('$');
-    fn alias_tok: ALIAS_TOK = just("alias");
-    fn enum_tok: ENUM_TOK = just("enum");
-    fn ident: IDENT = chumsky::text::ident();
+    fn deleted_ref: DELETED_REF = just("#REF!").labelled("a cell");
+    fn dollar: DOLLAR = just('$').labelled("`$alias`");
+    fn alias_tok: ALIAS_TOK = just("alias").labelled("`alias`");
+    fn enum_tok: ENUM_TOK = just("enum").labelled("`enum`");
+    fn ident: IDENT = chumsky::text::ident().labelled("a name");
     // note that we don't have any string escapes
-    fn str: STR = none_of('"').repeated().delimited_by(just('"'), just('"'));
-    fn comment: COMMENT = ws().then(just("//")).then(none_of('\n').repeated()).then(nl());
+    fn str: STR = none_of('"').repeated().delimited_by(just('"'), just('"')).labelled("a string");
+    fn comment: COMMENT = ws().then(just("//").labelled("a comment")).then(none_of('\n').repeated()).then(eol());
+    // never empty, so that recovering at the end of the file can't loop forever
+    fn error_tok: ERROR_TOK = none_of('\n').and_is(just("\r\n").not()).repeated().at_least(1);
+}
+
+/// The end of a statement or comment: a newline, or the end of a file that doesn't
+/// end with one.
+fn eol<'a>() -> impl CSTParser<'a> {
+    choice((nl(), ws().then_ignore(end().labelled("end of line"))))
 }
 
 // AAA123
@@ -56,7 +68,14 @@ fn cell<'a>() -> impl CSTParser<'a, ()> {
             .filter(char::is_ascii_alphabetic)
             .repeated()
             .at_least(1)
-            .then(any().filter(char::is_ascii_digit).repeated().at_least(1)),
+            .then(
+                any()
+                    .filter(char::is_ascii_digit)
+                    .repeated()
+                    .at_least(1)
+                    .labelled("a row number"),
+            )
+            .labelled("a cell"),
     )
 }
 
@@ -65,14 +84,16 @@ nodes! {
     fn cell_range: CELL_RANGE = cell().then(colon()).then(cell());
     // $foo
     fn alias_expr: ALIAS_EXPR = dollar().then(ident());
-    fn place: PLACE = choice((cell_range(), alias_expr(), cell()));
+    fn place: PLACE = choice((cell_range(), alias_expr(), cell(), deleted_ref()));
     fn enum_expr: ENUM_EXPR = enum_tok().then(place());
     fn expr: EXPR = choice((enum_expr(), int(), str(), place()));
     // A1 = 3
     fn assign: ASSIGN = place().then(eq()).then(expr());
     // alias foo = A1
     fn alias_stmt: ALIAS_STMT = alias_tok().then(ident()).then(eq()).then(place());
-    fn statement: STATEMENT = choice((alias_stmt(), assign())).then(nl());
+    fn statement: STATEMENT = choice((alias_stmt(), assign())).then(eol());
+    // the rest of a line that isn't a valid statement
+    fn error_line: ERROR = error_tok().then(eol());
 }
 
 #[rustfmt::skip]
@@ -80,9 +101,10 @@ pub(crate) fn parser<'a>() -> impl CSTParser<'a> {
     choice((
         comment(),
         ws().then_ignore(nl()),
-        statement(),
+        // keep going after a bad statement, so we report every one of them
+        statement().recover_with(via_parser(error_line())),
     ))
-    .repeated().then_ignore(end())
+    .repeated().then_ignore(ws()).then_ignore(end())
 }
 
 #[cfg(test)]
@@ -102,5 +124,15 @@ mod test {
     #[test]
     fn simple_comment() {
         ok("// foo\n", comment());
+        ok("// foo\r\n", comment());
+        ok("// foo", comment());
+    }
+
+    #[test]
+    fn line_endings() {
+        ok("A1 = 1\r\n\r\nA2 = 2\r\n", parser());
+        ok("A1 = 1\nA2 = 2", parser());
+        ok("A1 = 1\n  ", parser());
+        ok("", parser());
     }
 }
@@ -0,0 +1,228 @@
+//! Completions at the cursor.
+//!
+//! The line being typed usually doesn't parse yet, and error recovery turns it
+//! into an `ERROR` node without any structure. So what to complete is worked out
+//! from the text before the cursor on its line, and what to complete it with from
+//! the statements that did parse.
+
+use std::collections::BTreeMap;
+
+use cstree::text::{TextRange, TextSize};
+
+use crate::cell_index::{CellRef, Col, RangeRef};
+use crate::edit::write_place;
+use crate::eval::{Evaluated, Evaluator};
+use crate::sema::{Alias, Sheet, Value};
+use crate::Parse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Alias,
+    /// Cells an `enum` can take its options from.
+    Range,
+    /// One of the options of an enum cell.
+    EnumMember,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// The text to insert, which is also what editors show.
+    pub label: String,
+    pub kind: CompletionKind,
+    /// More about it, like the cells an alias refers to.
+    pub detail: Option<String>,
+    /// The text the completion replaces, which is what was typed of it so far.
+    pub span: TextRange,
+}
+
+/// What could be typed at `offset`:
+///
+/// - after `$`, the names of the aliases defined before it;
+/// - after `enum`, those aliases and the columns of strings in the sheet;
+/// - as the value of a cell that was assigned an enum, the options of that enum.
+pub fn completions(parse: &Parse, sheet: &Sheet, offset: TextSize) -> Vec<Completion> {
+    let source = parse.source();
+    let end = usize::from(offset);
+    if end > source.len() || !source.is_char_boundary(end) {
+        return Vec::new();
+    }
+    let line_start = source[..end].rfind('\n').map_or(0, |i| i + 1);
+    let before = &source[line_start..end];
+    if before.contains("//") {
+        return Vec::new();
+    }
+    // the part of a name or cell that was already typed
+    let rest = before.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    let typed = TextRange::new(TextSize::from((line_start + rest.len()) as u32), offset);
+
+    if rest.ends_with('$') {
+        return aliases(sheet, offset)
+            .map(|alias| Completion {
+                label: alias.name.clone(),
+                kind: CompletionKind::Alias,
+                detail: Some(write_place(alias.target)),
+                span: typed,
+            })
+            .collect();
+    }
+    let after_enum = rest
+        .trim_end()
+        .strip_suffix("enum")
+        .is_some_and(|s| s.ends_with(|c: char| c.is_whitespace() || c == '='));
+    if after_enum && rest.ends_with(char::is_whitespace) {
+        return enum_sources(sheet, offset, typed);
+    }
+
+    let Some((place, value)) = before.split_once('=') else {
+        return Vec::new();
+    };
+    let Ok(cell) = place.trim().parse::<CellRef>() else {
+        return Vec::new();
+    };
+    let value = value.trim_start();
+    let unfinished_string = value.starts_with('"') && !value[1..].contains('"');
+    if !value.is_empty() && !unfinished_string {
+        return Vec::new();
+    }
+    let typed = TextRange::new(offset - TextSize::from(value.len() as u32), offset);
+    let line_start = TextSize::from(line_start as u32);
+    enum_options(sheet, cell, line_start)
+        .into_iter()
+        .map(|option| Completion {
+            label: option.to_string(),
+            kind: CompletionKind::EnumMember,
+            detail: None,
+            span: typed,
+        })
+        .collect()
+}
+
+/// The aliases that can be used at `offset`, by name.
+fn aliases(sheet: &Sheet, offset: TextSize) -> impl Iterator<Item = &Alias> {
+    // the latest definition of each name wins
+    let aliases: BTreeMap<_, _> = sheet
+        .definitions
+        .iter()
+        .filter(|alias| alias.span.end() <= offset)
+        .map(|alias| (&alias.name, alias))
+        .collect();
+    aliases.into_values()
+}
+
+/// What an `enum` at `offset` can take its options from: the aliases, and the
+/// strings in each column, which are usually lists of options.
+fn enum_sources(sheet: &Sheet, offset: TextSize, typed: TextRange) -> Vec<Completion> {
+    let mut completions: Vec<_> = aliases(sheet, offset)
+        .map(|alias| Completion {
+            label: format!("${}", alias.name),
+            kind: CompletionKind::Alias,
+            detail: Some(write_place(alias.target)),
+            span: typed,
+        })
+        .collect();
+    let mut columns: BTreeMap<Col, RangeRef> = BTreeMap::new();
+    for (cell, assignment) in sheet.cells() {
+        if let Value::Str(_) = assignment.value {
+            columns
+                .entry(cell.col)
+                .and_modify(|range| *range = RangeRef::new(range.start(), cell))
+                .or_insert(cell.into());
+        }
+    }
+    completions.extend(columns.into_values().map(|range| Completion {
+        label: range.to_string(),
+        kind: CompletionKind::Range,
+        detail: None,
+        span: typed,
+    }));
+    completions
+}
+
+/// The options of the enum last assigned to `cell` before `line_start`.
+fn enum_options(sheet: &Sheet, cell: CellRef, line_start: TextSize) -> Vec<Evaluated> {
+    let range = sheet
+        .history(cell)
+        .take_while(|assignment| assignment.span.start() < line_start)
+        .filter_map(|assignment| match assignment.value {
+            Value::Enum(range) => Some(range),
+            _ => None,
+        })
+        .last();
+    let mut options = Vec::new();
+    let mut evaluator = Evaluator::new(sheet);
+    for cell in range.into_iter().flat_map(RangeRef::cells) {
+        if let Ok(Some(option)) = evaluator.eval(cell) {
+            if !options.contains(&option) {
+                options.push(option);
+            }
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use super::*;
+
+    /// The completions at the end of `source`, and the text each one replaces.
+    fn complete(source: &str) -> Vec<(String, &str)> {
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        completions(&parse, &sheet, TextSize::from(source.len() as u32))
+            .into_iter()
+            .map(|completion| {
+                let typed = &source[Range::<usize>::from(completion.span)];
+                (completion.label, typed)
+            })
+            .collect()
+    }
+
+    fn labels(source: &str) -> Vec<String> {
+        complete(source)
+            .into_iter()
+            .map(|(label, _)| label)
+            .collect()
+    }
+
+    #[test]
+    fn after_dollar() {
+        let source = "alias veg = B1\nalias fruits = A1:A3\nC1 = $fr";
+        assert_eq!(
+            complete(source),
+            [("fruits".to_owned(), "fr"), ("veg".to_owned(), "fr")]
+        );
+        // only the aliases defined before the cursor
+        let source = "alias a = A1\nB1 = $\nalias b = A2\n";
+        let cursor = TextSize::from(source.find('$').unwrap() as u32 + 1);
+        let parse = crate::parse(source);
+        let completions = completions(&parse, &crate::analyze(&parse), cursor);
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].label, "a");
+        assert_eq!(completions[0].detail.as_deref(), Some("A1"));
+        assert!(labels("// B1 = $").is_empty());
+    }
+
+    #[test]
+    fn after_enum() {
+        let source = "A1 = \"apple\"\nA2 = \"banana\"\nB1 = 3\nalias fruits = A1:A2\nC1 = enum ";
+        assert_eq!(labels(source), ["$fruits", "A1:A2"]);
+        assert_eq!(labels(&format!("{source}$")), ["fruits"]);
+    }
+
+    #[test]
+    fn enum_members() {
+        let source = "A1 = \"apple\"\nA2 = \"banana\"\nA3 = \"apple\"\nB1 = enum A1:A3\n";
+        assert_eq!(
+            complete(&format!("{source}B1 = \"b")),
+            [
+                ("\"apple\"".to_owned(), "\"b"),
+                ("\"banana\"".to_owned(), "\"b")
+            ]
+        );
+        assert_eq!(labels(&format!("{source}B1 = ")).len(), 2);
+        assert!(labels(&format!("{source}A1 = ")).is_empty());
+        assert!(labels(&format!("{source}B1 = \"apple\"")).is_empty());
+    }
+}
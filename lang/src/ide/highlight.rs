@@ -0,0 +1,345 @@
+//! Syntax highlighting, classifying the tokens of the CST with what the
+//! [semantic model](crate::sema) knows about them.
+
+use std::collections::HashSet;
+use std::ops::{BitOr, Range};
+
+use cstree::text::{TextRange, TextSize};
+use cstree::util::NodeOrToken;
+
+use crate::parser::{Parse, SyntaxKind::*, SyntaxToken};
+use crate::sema::{self, Sheet, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClass {
+    Cell,
+    /// A range of cells, like `A1:A3`, as a whole.
+    Range,
+    /// The name of an alias, or a use of it like `$fruits` as a whole.
+    Alias,
+    Keyword,
+    Number,
+    String,
+    /// A string that is one of the options of an enum, or that is assigned to a
+    /// cell holding an enum.
+    EnumMember,
+    Comment,
+    /// A `//@` comment.
+    Directive,
+}
+
+impl TokenClass {
+    /// Every class, in the order of their discriminants.
+    pub const ALL: [TokenClass; 9] = [
+        TokenClass::Cell,
+        TokenClass::Range,
+        TokenClass::Alias,
+        TokenClass::Keyword,
+        TokenClass::Number,
+        TokenClass::String,
+        TokenClass::EnumMember,
+        TokenClass::Comment,
+        TokenClass::Directive,
+    ];
+
+    /// The name of the class, as used for CSS classes.
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenClass::Cell => "cell",
+            TokenClass::Range => "range",
+            TokenClass::Alias => "alias",
+            TokenClass::Keyword => "keyword",
+            TokenClass::Number => "number",
+            TokenClass::String => "string",
+            TokenClass::EnumMember => "enum-member",
+            TokenClass::Comment => "comment",
+            TokenClass::Directive => "directive",
+        }
+    }
+}
+
+/// A set of modifiers of a token, as bit flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers(u32);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    /// The name of an alias in its `alias` statement.
+    pub const DEFINITION: Modifiers = Modifiers(1 << 0);
+    /// A use of an alias that no `alias` statement defines before it, or a
+    /// `#REF!` to deleted cells.
+    pub const UNDEFINED: Modifiers = Modifiers(1 << 1);
+
+    /// Every modifier, in the order of their bits.
+    pub const ALL: [Modifiers; 2] = [Modifiers::DEFINITION, Modifiers::UNDEFINED];
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The name of a single modifier, as used for CSS classes.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Modifiers::DEFINITION => Some("definition"),
+            Modifiers::UNDEFINED => Some("undefined"),
+            _ => None,
+        }
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, rhs: Modifiers) -> Modifiers {
+        Modifiers(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Highlight {
+    pub span: TextRange,
+    pub class: TokenClass,
+    pub modifiers: Modifiers,
+}
+
+/// Classify the tokens of a file, in source order. The spans don't overlap, and
+/// only strings can include whitespace or even span several lines. Tokens that
+/// need no highlighting, like `=`, are left out.
+pub fn highlight(parse: &Parse, sheet: &Sheet) -> Vec<Highlight> {
+    let members = enum_members(sheet);
+    let undefined: HashSet<_> = sheet
+        .references
+        .iter()
+        .filter(|reference| reference.definition.is_none())
+        .map(|reference| reference.span)
+        .collect();
+    let root = parse.red_tree();
+    let mut highlights = Vec::new();
+    let mut push = |span, class, modifiers| {
+        highlights.push(Highlight {
+            span,
+            class,
+            modifiers,
+        })
+    };
+    for elem in root.descendants_with_tokens() {
+        let token = match elem {
+            NodeOrToken::Node(node) => {
+                match node.kind() {
+                    CELL_RANGE => push(
+                        sema::trimmed_range(node),
+                        TokenClass::Range,
+                        Modifiers::NONE,
+                    ),
+                    ALIAS_EXPR => {
+                        let name = node
+                            .children_with_tokens()
+                            .filter_map(|elem| elem.into_token())
+                            .find(|token| token.kind() == IDENT);
+                        let modifiers = match name {
+                            Some(name) if undefined.contains(&name.text_range()) => {
+                                Modifiers::UNDEFINED
+                            }
+                            _ => Modifiers::NONE,
+                        };
+                        push(sema::trimmed_range(node), TokenClass::Alias, modifiers);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            NodeOrToken::Token(token) => token,
+        };
+        // the tokens of ranges and alias uses are covered by their node
+        if matches!(token.parent().kind(), CELL_RANGE | ALIAS_EXPR) {
+            continue;
+        }
+        let span = token.text_range();
+        match token.kind() {
+            CELL => push(span, TokenClass::Cell, Modifiers::NONE),
+            DELETED_REF => push(span, TokenClass::Cell, Modifiers::UNDEFINED),
+            IDENT => push(span, TokenClass::Alias, Modifiers::DEFINITION),
+            ALIAS_TOK | ENUM_TOK => push(span, TokenClass::Keyword, Modifiers::NONE),
+            INT => push(span, TokenClass::Number, Modifiers::NONE),
+            STR => {
+                let class = match assigned_place(token) {
+                    Some(place) if members.contains(&place) => TokenClass::EnumMember,
+                    _ => TokenClass::String,
+                };
+                push(span, class, Modifiers::NONE);
+            }
+            COMMENT => {
+                let text = parse.text(token);
+                let comment = text.trim();
+                let start =
+                    span.start() + TextSize::of(&text[..text.len() - text.trim_start().len()]);
+                let class = if comment.starts_with("//@") {
+                    TokenClass::Directive
+                } else {
+                    TokenClass::Comment
+                };
+                push(
+                    TextRange::at(start, TextSize::of(comment)),
+                    class,
+                    Modifiers::NONE,
+                );
+            }
+            _ => {}
+        }
+    }
+    highlights
+}
+
+/// The places of the assignments of strings that are enum members.
+fn enum_members(sheet: &Sheet) -> HashSet<TextRange> {
+    let options: Vec<_> = sheet
+        .assignments
+        .iter()
+        .filter_map(|assignment| match assignment.value {
+            Value::Enum(range) => Some(range),
+            _ => None,
+        })
+        .collect();
+    let mut enums = Vec::new();
+    let mut members = HashSet::new();
+    for assignment in &sheet.assignments {
+        match assignment.value {
+            Value::Enum(_) => enums.push(assignment.target),
+            Value::Str(_) => {
+                let target = assignment.target;
+                if options
+                    .iter()
+                    .chain(&enums)
+                    .any(|range| range.intersects(target))
+                {
+                    members.insert(assignment.span);
+                }
+            }
+            _ => {}
+        }
+    }
+    members
+}
+
+/// The place of the assignment whose value is `token`.
+fn assigned_place(token: &SyntaxToken) -> Option<TextRange> {
+    let assign = token
+        .parent()
+        .parent()
+        .filter(|node| node.kind() == ASSIGN)?;
+    assign
+        .first_child()
+        .filter(|node| node.kind() == PLACE)
+        .map(sema::trimmed_range)
+}
+
+/// Render `source` as HTML, with each highlight in a `<span>` whose classes are
+/// the name of its class and of its modifiers, like
+/// `<span class="alias undefined">$fruits</span>`.
+pub fn html(source: &str, highlights: &[Highlight]) -> String {
+    let mut out = String::from("<pre class=\"ssl\"><code>");
+    let mut end = 0;
+    for highlight in highlights {
+        let Range { start, end: next } = Range::<usize>::from(highlight.span);
+        escape(&mut out, &source[end..start]);
+        out.push_str("<span class=\"");
+        out.push_str(highlight.class.name());
+        for modifier in Modifiers::ALL {
+            if highlight.modifiers.contains(modifier) {
+                out.push(' ');
+                out.push_str(modifier.name().unwrap());
+            }
+        }
+        out.push_str("\">");
+        escape(&mut out, &source[start..next]);
+        out.push_str("</span>");
+        end = next;
+    }
+    escape(&mut out, &source[end..]);
+    out.push_str("</code></pre>\n");
+    out
+}
+
+fn escape(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn classes(source: &str) -> Vec<(&str, TokenClass, Modifiers)> {
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        highlight(&parse, &sheet)
+            .into_iter()
+            .map(|highlight| {
+                let text = &source[Range::<usize>::from(highlight.span)];
+                (text, highlight.class, highlight.modifiers)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn classify() {
+        use TokenClass::*;
+        let none = Modifiers::NONE;
+        let source = "//@ allow(unused-alias)\n// fruits\nA1 = \"apple\"\nA2 = 3\n\
+            alias fruits = A1:A2\nB1 = enum $fruits\nB1 = \"apple\"\nC1 = $veg\n";
+        assert_eq!(
+            classes(source),
+            [
+                ("//@ allow(unused-alias)", Directive, none),
+                ("// fruits", Comment, none),
+                ("A1", Cell, none),
+                ("\"apple\"", EnumMember, none),
+                ("A2", Cell, none),
+                ("3", Number, none),
+                ("alias", Keyword, none),
+                ("fruits", Alias, Modifiers::DEFINITION),
+                ("A1:A2", Range, none),
+                ("B1", Cell, none),
+                ("enum", Keyword, none),
+                ("$fruits", Alias, none),
+                ("B1", Cell, none),
+                ("\"apple\"", EnumMember, none),
+                ("C1", Cell, none),
+                ("$veg", Alias, Modifiers::UNDEFINED),
+            ]
+        );
+        assert_eq!(
+            classes("A1 = \"apple\"\n"),
+            [("A1", Cell, none), ("\"apple\"", String, none)]
+        );
+        assert_eq!(
+            classes("A1 = #REF!\n"),
+            [("A1", Cell, none), ("#REF!", Cell, Modifiers::UNDEFINED)]
+        );
+    }
+
+    #[test]
+    fn render() {
+        let source = "A1 = \"<b>\"\nB1 = $x\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        assert_eq!(
+            html(source, &highlight(&parse, &sheet)),
+            "<pre class=\"ssl\"><code><span class=\"cell\">A1</span> = \
+            <span class=\"string\">&quot;&lt;b&gt;&quot;</span>\n\
+            <span class=\"cell\">B1</span> = <span class=\"alias undefined\">$x</span>\n\
+            </code></pre>\n"
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! What to show when hovering a cell or an alias: the value a cell ends up with,
+//! what kind of value it is, and the statement that gives it that value.
+//!
+//! The language server shows the [markdown](Hover::markdown) of a hover as is.
+//! The grid has no cursor in a file, so it describes cells with [`hover_cell`].
+
+use std::fmt::{self, Write};
+
+use cstree::text::{TextRange, TextSize};
+
+use super::navigation::{symbol_at, token_at, Symbol};
+use crate::cell_index::{CellRef, RangeRef};
+use crate::edit::write_place;
+use crate::eval::{self, EvalError, Evaluated, Evaluator};
+use crate::parser::{Parse, SyntaxKind, SyntaxKind::*, SyntaxNode};
+use crate::sema::{Sheet, Value};
+
+/// At most this many cells of an alias are described.
+const MAX_CELLS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hover {
+    /// The hovered cell, or name of an alias.
+    pub span: TextRange,
+    /// The alias that was hovered, if any.
+    pub alias: Option<AliasHover>,
+    /// The hovered cell, or the first [`MAX_CELLS`] cells of the alias that were
+    /// assigned, column by column.
+    pub cells: Vec<CellHover>,
+    /// How many more cells of the alias were assigned.
+    pub more: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasHover {
+    pub name: String,
+    pub target: RangeRef,
+    /// The `alias` statement.
+    pub definition: Statement,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellHover {
+    pub cell: CellRef,
+    /// `Ok(None)` if nothing assigns the cell.
+    pub value: Result<Option<Evaluated>, EvalError>,
+    pub ty: Option<Type>,
+    /// The cells whose `enum` takes one of its options from this cell.
+    pub enums: Vec<CellRef>,
+    /// The assignment that gives the cell its value.
+    pub definition: Option<Statement>,
+}
+
+/// The kind of value a cell holds. A reference has the type of what it refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Number,
+    String,
+    /// One of the values of the given cells.
+    Enum(RangeRef),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Number => f.write_str("number"),
+            Type::String => f.write_str("string"),
+            Type::Enum(range) => write!(f, "enum {range}"),
+        }
+    }
+}
+
+/// A statement of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub span: TextRange,
+    pub text: String,
+}
+
+/// Describe the cell or alias at `offset`.
+pub fn hover(parse: &Parse, sheet: &Sheet, offset: TextSize) -> Option<Hover> {
+    let root = parse.red_tree();
+    let span = token_at(&root, offset)?.text_range();
+    let mut cx = Hoverer::new(parse, sheet, root);
+    match symbol_at(parse, sheet, offset)? {
+        Symbol::Cell(cell) => Some(Hover {
+            span,
+            alias: None,
+            cells: vec![cx.cell(cell)],
+            more: 0,
+        }),
+        Symbol::Alias(i) => {
+            let alias = &sheet.definitions[i];
+            let (cells, more) = if alias.target.start() == alias.target.end() {
+                (vec![cx.cell(alias.target.start())], 0)
+            } else {
+                let mut assigned = sheet.cells_in(alias.target).map(|(cell, _)| cell);
+                let cells = assigned.by_ref().take(MAX_CELLS).map(|cell| cx.cell(cell));
+                (cells.collect(), assigned.count())
+            };
+            let alias = AliasHover {
+                name: alias.name.clone(),
+                target: alias.target,
+                definition: statement(parse, &cx.root, ALIAS_STMT, alias.span)?,
+            };
+            Some(Hover {
+                span,
+                alias: Some(alias),
+                cells,
+                more,
+            })
+        }
+    }
+}
+
+/// Describe `cell`, whether or not it is assigned.
+pub fn hover_cell(parse: &Parse, sheet: &Sheet, cell: CellRef) -> CellHover {
+    Hoverer::new(parse, sheet, parse.red_tree()).cell(cell)
+}
+
+/// What describing several cells of the same sheet shares.
+struct Hoverer<'a> {
+    parse: &'a Parse,
+    sheet: &'a Sheet,
+    root: SyntaxNode,
+    evaluator: Evaluator<'a>,
+    /// Every cell holding an enum, with the cells it takes its options from.
+    enums: Vec<(CellRef, RangeRef)>,
+}
+
+impl<'a> Hoverer<'a> {
+    fn new(parse: &'a Parse, sheet: &'a Sheet, root: SyntaxNode) -> Self {
+        let enums = sheet
+            .cells()
+            .filter_map(|(cell, assignment)| match assignment.value {
+                Value::Enum(options) => Some((cell, options)),
+                _ => None,
+            })
+            .collect();
+        Hoverer {
+            parse,
+            sheet,
+            root,
+            evaluator: Evaluator::new(sheet),
+            enums,
+        }
+    }
+
+    fn cell(&mut self, cell: CellRef) -> CellHover {
+        let enums = self
+            .enums
+            .iter()
+            .filter(|(_, options)| options.contains(cell))
+            .map(|&(cell, _)| cell)
+            .collect();
+        let definition = self
+            .sheet
+            .get(cell)
+            .and_then(|assignment| statement(self.parse, &self.root, ASSIGN, assignment.span));
+        CellHover {
+            cell,
+            value: self.evaluator.eval(cell),
+            ty: ty(self.sheet, cell),
+            enums,
+            definition,
+        }
+    }
+}
+
+/// The type of `cell`, following references until a value. `None` if the cell
+/// isn't assigned or its value depends on itself.
+fn ty(sheet: &Sheet, mut cell: CellRef) -> Option<Type> {
+    let mut seen = Vec::new();
+    loop {
+        let assignment = sheet.get(cell)?;
+        match assignment.value {
+            Value::Int(_) => return Some(Type::Number),
+            Value::Str(_) => return Some(Type::String),
+            Value::Enum(range) => return Some(Type::Enum(range)),
+            Value::Ref(range) => {
+                if seen.contains(&cell) {
+                    return None;
+                }
+                seen.push(cell);
+                cell = eval::source_cell(assignment.target, range, cell)?;
+            }
+        }
+    }
+}
+
+/// The statement of the given kind that contains `span`.
+fn statement(
+    parse: &Parse,
+    root: &SyntaxNode,
+    kind: SyntaxKind,
+    span: TextRange,
+) -> Option<Statement> {
+    let node = root
+        .descendants()
+        .find(|node| node.kind() == kind && node.text_range().contains_range(span))?;
+    let text: String = node
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .map(|token| parse.text(token))
+        .collect();
+    Some(Statement {
+        span: node.text_range(),
+        text: text.trim().to_owned(),
+    })
+}
+
+impl Hover {
+    /// The hover as markdown, for editors and tooltips.
+    pub fn markdown(&self) -> String {
+        let mut out = String::new();
+        if let Some(alias) = &self.alias {
+            let target = write_place(alias.target);
+            writeln!(out, "**`${}`**: alias for `{target}`", alias.name).unwrap();
+            code_block(&mut out, &alias.definition);
+        }
+        for (i, cell) in self.cells.iter().enumerate() {
+            if i > 0 || self.alias.is_some() {
+                out.push_str("\n---\n\n");
+            }
+            cell.write_markdown(&mut out);
+        }
+        if self.more > 0 {
+            write!(out, "\n…and {} more cells\n", self.more).unwrap();
+        }
+        out
+    }
+}
+
+impl CellHover {
+    fn write_markdown(&self, out: &mut String) {
+        let cell = self.cell;
+        match (&self.value, self.ty) {
+            (Ok(None), _) => writeln!(out, "**`{cell}`**: empty"),
+            (Ok(Some(value)), Some(ty)) => writeln!(out, "**`{cell}`**: {ty} = `{value}`"),
+            (Ok(Some(value)), None) => writeln!(out, "**`{cell}`** = `{value}`"),
+            (Err(EvalError::Cycle(_)), _) => {
+                writeln!(out, "**`{cell}`**: depends on itself")
+            }
+            (Err(EvalError::ShapeMismatch(_)), _) => writeln!(
+                out,
+                "**`{cell}`**: assigned from a range of a different size"
+            ),
+        }
+        .unwrap();
+        if let Some(definition) = &self.definition {
+            code_block(out, definition);
+        }
+        if !self.enums.is_empty() {
+            let enums: Vec<_> = self.enums.iter().map(|cell| format!("`{cell}`")).collect();
+            write!(out, "\nan option of the enum in {}\n", enums.join(", ")).unwrap();
+        }
+    }
+}
+
+fn code_block(out: &mut String, statement: &Statement) {
+    write!(out, "\n```ssl\n{}\n```\n", statement.text).unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "A1 = 3\nalias num = A1\nA2 = $num\nA3 = \"three\"\n\
+        B1 = enum A1:A3\nalias column = A1:A9\nC1 = C1\n";
+
+    fn hover_at(needle: &str) -> Hover {
+        let parse = crate::parse(SOURCE);
+        let sheet = crate::analyze(&parse);
+        let offset = TextSize::from(SOURCE.find(needle).unwrap() as u32);
+        hover(&parse, &sheet, offset).unwrap()
+    }
+
+    fn cell(name: &str) -> CellRef {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn cells() {
+        let hover = hover_at("A2 =");
+        assert_eq!(hover.alias, None);
+        let [a2] = &hover.cells[..] else {
+            panic!("expected one cell, got {:?}", hover.cells)
+        };
+        assert_eq!(a2.value, Ok(Some(Evaluated::Int(3))));
+        assert_eq!(a2.ty, Some(Type::Number));
+        assert_eq!(a2.enums, [cell("B1")]);
+        assert_eq!(a2.definition.as_ref().unwrap().text, "A2 = $num");
+
+        let a1 = &hover_at("A1 =").cells[0];
+        assert_eq!(a1.definition.as_ref().unwrap().text, "A1 = 3");
+
+        let b1 = &hover_at("B1").cells[0];
+        let range = RangeRef::new(cell("A1"), cell("A3"));
+        assert_eq!(b1.ty, Some(Type::Enum(range)));
+        assert!(b1.enums.is_empty());
+
+        let c1 = &hover_at("C1").cells[0];
+        assert_eq!(c1.value, Err(EvalError::Cycle(cell("C1"))));
+        assert_eq!(c1.ty, None);
+
+        let a9 = &hover_at("A9").cells[0];
+        assert_eq!(a9.value, Ok(None));
+        assert_eq!(a9.definition, None);
+    }
+
+    #[test]
+    fn aliases() {
+        // the definition and a use describe the same alias
+        let num = hover_at("num =");
+        assert_eq!(hover_at("num\nA3").alias, num.alias);
+        let alias = num.alias.unwrap();
+        assert_eq!(alias.definition.text, "alias num = A1");
+        assert_eq!(alias.target, RangeRef::from(cell("A1")));
+        assert_eq!(num.cells.len(), 1);
+        assert_eq!(num.cells[0].cell, cell("A1"));
+        // only the cells that were assigned
+        let column = hover_at("column");
+        let cells: Vec<_> = column.cells.iter().map(|hover| hover.cell).collect();
+        assert_eq!(cells, [cell("A1"), cell("A2"), cell("A3")]);
+        assert_eq!(column.more, 0);
+
+        // only the first cells of a large alias are described
+        let source = "A1:A25 = 1\nalias ones = A1:A25\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let offset = TextSize::from(source.find("ones").unwrap() as u32);
+        let ones = hover(&parse, &sheet, offset).unwrap();
+        assert_eq!(ones.cells.len(), MAX_CELLS);
+        assert_eq!(ones.more, 25 - MAX_CELLS);
+        assert!(ones.markdown().ends_with("\n…and 15 more cells\n"));
+    }
+
+    #[test]
+    fn markdown() {
+        assert_eq!(
+            hover_at("A2 =").markdown(),
+            "**`A2`**: number = `3`\n\n```ssl\nA2 = $num\n```\n\nan option of the enum in `B1`\n"
+        );
+        assert_eq!(
+            hover_at("num =").markdown(),
+            "**`$num`**: alias for `A1`\n\n```ssl\nalias num = A1\n```\n\n---\n\n\
+            **`A1`**: number = `3`\n\n```ssl\nA1 = 3\n```\n\nan option of the enum in `B1`\n"
+        );
+    }
+}
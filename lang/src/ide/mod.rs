@@ -0,0 +1,17 @@
+//! Queries for editors, on top of the [semantic model](crate::sema).
+//!
+//! They take the offset of the cursor and answer with ranges of the source, which
+//! the language server and the app turn into positions of their own.
+
+mod completions;
+mod highlight;
+mod hover;
+mod navigation;
+
+pub use completions::{completions, Completion, CompletionKind};
+pub use highlight::{highlight, html, Highlight, Modifiers, TokenClass};
+pub use hover::{hover, hover_cell, AliasHover, CellHover, Hover, Statement, Type};
+pub use navigation::{definition, references, rename, symbol_at, writes, RenameError, Symbol};
+
+pub use crate::TextEdit;
+pub(crate) use navigation::is_alias_name;
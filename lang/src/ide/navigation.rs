@@ -0,0 +1,386 @@
+//! Go-to-definition, find-references and rename, for aliases and cells.
+//!
+//! Aliases are resolved by [`crate::sema`]: each `$name` refers to the latest
+//! `alias` statement of that name before it. Cells have no declaration, so the
+//! definition of a cell is the assignment that gives it its value.
+
+use std::fmt;
+
+use cstree::text::{TextRange, TextSize};
+use cstree::util::NodeOrToken;
+
+use crate::cell_index::{CellRef, RangeRef};
+use crate::parser::{Parse, SyntaxKind::*, SyntaxNode, SyntaxToken, TextEdit};
+use crate::sema::{self, Sheet};
+
+/// Something a cursor can be on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    /// An alias, by the index of its `alias` statement in [`Sheet::definitions`].
+    Alias(usize),
+    Cell(CellRef),
+}
+
+/// The alias or cell at `offset`. A cursor right after a name, like at the end
+/// of `$fruits`, is still on it.
+pub fn symbol_at(parse: &Parse, sheet: &Sheet, offset: TextSize) -> Option<Symbol> {
+    let root = parse.red_tree();
+    let token = token_at(&root, offset)?;
+    match token.kind() {
+        CELL => parse.text(token).parse().ok().map(Symbol::Cell),
+        IDENT => {
+            let span = token.text_range();
+            if let Some(i) = sheet
+                .definitions
+                .iter()
+                .position(|alias| alias.span == span)
+            {
+                return Some(Symbol::Alias(i));
+            }
+            let reference = sheet
+                .references
+                .iter()
+                .find(|reference| reference.span == span)?;
+            reference.definition.map(Symbol::Alias)
+        }
+        _ => None,
+    }
+}
+
+pub(super) fn token_at(root: &SyntaxNode, offset: TextSize) -> Option<&SyntaxToken> {
+    root.descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .filter(|token| matches!(token.kind(), CELL | IDENT))
+        .find(|token| token.text_range().contains_inclusive(offset))
+}
+
+/// Where the symbol at `offset` is defined: the name in its `alias` statement,
+/// or the place of the assignment that gives a cell its value.
+pub fn definition(parse: &Parse, sheet: &Sheet, offset: TextSize) -> Option<TextRange> {
+    match symbol_at(parse, sheet, offset)? {
+        Symbol::Alias(i) => Some(sheet.definitions[i].span),
+        Symbol::Cell(cell) => sheet.get(cell).map(|assignment| assignment.span),
+    }
+}
+
+/// Every mention of the symbol at `offset`, in source order. For an alias, that
+/// is its name in the `alias` statement and every `$name` that refers to it; for
+/// a cell, every reference to it and every range that covers it.
+pub fn references(parse: &Parse, sheet: &Sheet, offset: TextSize) -> Vec<TextRange> {
+    match symbol_at(parse, sheet, offset) {
+        Some(Symbol::Alias(i)) => alias_mentions(sheet, i),
+        Some(Symbol::Cell(cell)) => cell_mentions(parse, cell)
+            .iter()
+            .map(Mention::span)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The place of every assignment that writes `cell`, oldest first.
+pub fn writes(sheet: &Sheet, cell: CellRef) -> Vec<TextRange> {
+    sheet
+        .history(cell)
+        .map(|assignment| assignment.span)
+        .collect()
+}
+
+fn alias_mentions(sheet: &Sheet, definition: usize) -> Vec<TextRange> {
+    // A use always comes after the definition it refers to, so this is sorted.
+    let uses = sheet
+        .references
+        .iter()
+        .filter(|reference| reference.definition == Some(definition))
+        .map(|reference| reference.span);
+    std::iter::once(sheet.definitions[definition].span)
+        .chain(uses)
+        .collect()
+}
+
+enum Mention {
+    /// The cell itself.
+    Cell(TextRange),
+    /// A range that covers the cell.
+    Range(TextRange),
+}
+
+impl Mention {
+    fn span(&self) -> TextRange {
+        match *self {
+            Mention::Cell(span) | Mention::Range(span) => span,
+        }
+    }
+}
+
+fn cell_mentions(parse: &Parse, cell: CellRef) -> Vec<Mention> {
+    let parse_cell = |token: &SyntaxToken| parse.text(token).parse::<CellRef>().ok();
+    let root = parse.red_tree();
+    let mut mentions = Vec::new();
+    for elem in root.descendants_with_tokens() {
+        match elem {
+            NodeOrToken::Node(node) if node.kind() == CELL_RANGE => {
+                let mut cells = node
+                    .children_with_tokens()
+                    .filter_map(|elem| elem.into_token())
+                    .filter(|token| token.kind() == CELL)
+                    .map(parse_cell);
+                if let (Some(Some(start)), Some(Some(end))) = (cells.next(), cells.next()) {
+                    if RangeRef::new(start, end).contains(cell) {
+                        mentions.push(Mention::Range(sema::trimmed_range(node)));
+                    }
+                }
+            }
+            NodeOrToken::Token(token)
+                if token.kind() == CELL && token.parent().kind() != CELL_RANGE =>
+            {
+                if parse_cell(token) == Some(cell) {
+                    mentions.push(Mention::Cell(token.text_range()));
+                }
+            }
+            _ => {}
+        }
+    }
+    mentions
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameError {
+    /// The cursor isn't on an alias or a cell.
+    NothingToRename,
+    /// The new name isn't a valid alias name, or isn't a cell when renaming a cell.
+    InvalidName(String),
+    /// Renaming would change what something else refers to. `span` is where the
+    /// new name is already defined or used.
+    Taken { name: String, span: TextRange },
+    /// The cell is part of the range at this span, which can't follow it to its
+    /// new name.
+    InRange(TextRange),
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::NothingToRename => f.write_str("only aliases and cells can be renamed"),
+            RenameError::InvalidName(name) => write!(f, "`{name}` is not a valid name here"),
+            RenameError::Taken { name, .. } => write!(f, "`{name}` is already used"),
+            RenameError::InRange(_) => f.write_str("the cell is part of a range"),
+        }
+    }
+}
+
+impl std::error::Error for RenameError {}
+
+/// Rename the symbol at `offset`, returning an edit for each of its mentions in
+/// source order. Every edit replaces a single token.
+///
+/// Renaming a cell moves it: every reference to it is rewritten, which is only
+/// possible if no range covers it.
+pub fn rename(
+    parse: &Parse,
+    sheet: &Sheet,
+    offset: TextSize,
+    new_name: &str,
+) -> Result<Vec<TextEdit>, RenameError> {
+    let edits = |spans: Vec<TextRange>, replacement: String| -> Vec<TextEdit> {
+        spans
+            .into_iter()
+            .map(|span| TextEdit {
+                span,
+                replacement: replacement.clone(),
+            })
+            .collect()
+    };
+    match symbol_at(parse, sheet, offset).ok_or(RenameError::NothingToRename)? {
+        Symbol::Alias(i) => {
+            if !is_alias_name(new_name) {
+                return Err(RenameError::InvalidName(new_name.to_owned()));
+            }
+            if new_name != sheet.definitions[i].name {
+                // Another alias of that name, or a use of an undefined one, would
+                // start referring to this alias or stop doing so.
+                let defined = sheet
+                    .definitions
+                    .iter()
+                    .map(|alias| (&alias.name, alias.span));
+                let undefined = sheet
+                    .references
+                    .iter()
+                    .filter(|reference| reference.definition.is_none())
+                    .map(|reference| (&reference.name, reference.span));
+                if let Some((_, span)) =
+                    defined.chain(undefined).find(|(name, _)| *name == new_name)
+                {
+                    return Err(RenameError::Taken {
+                        name: new_name.to_owned(),
+                        span,
+                    });
+                }
+            }
+            Ok(edits(alias_mentions(sheet, i), new_name.to_owned()))
+        }
+        Symbol::Cell(cell) => {
+            let new_cell: CellRef = new_name
+                .parse()
+                .map_err(|_| RenameError::InvalidName(new_name.to_owned()))?;
+            let mentions = cell_mentions(parse, cell);
+            for mention in &mentions {
+                if let Mention::Range(span) = *mention {
+                    return Err(RenameError::InRange(span));
+                }
+            }
+            if new_cell != cell {
+                if let Some(mention) = cell_mentions(parse, new_cell).first() {
+                    return Err(RenameError::Taken {
+                        name: new_cell.to_string(),
+                        span: mention.span(),
+                    });
+                }
+            }
+            let spans = mentions.iter().map(Mention::span).collect();
+            Ok(edits(spans, new_cell.to_string()))
+        }
+    }
+}
+
+/// Whether the grammar accepts `name` as the name of an alias.
+pub(crate) fn is_alias_name(name: &str) -> bool {
+    let parse = crate::parse(&format!("alias {name} = A1\n"));
+    let root = parse.red_tree();
+    parse.errors.is_empty()
+        && root
+            .descendants_with_tokens()
+            .filter_map(|elem| elem.into_token())
+            .any(|token| token.kind() == IDENT && parse.text(token) == name)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use super::*;
+
+    const SHEET: &str = "\
+A1 = \"apple\"
+A2 = \"banana\"
+alias fruits = A1:A2
+// the favourite one
+alias best = A2
+B1 = enum $fruits
+C1:C2 = $fruits
+A2 = \"cherry\"
+";
+
+    /// The offset right after the first occurrence of `needle`.
+    fn after(source: &str, needle: &str) -> TextSize {
+        TextSize::from((source.find(needle).unwrap() + needle.len()) as u32)
+    }
+
+    fn texts<'s>(source: &'s str, spans: &[TextRange]) -> Vec<&'s str> {
+        spans
+            .iter()
+            .map(|&span| &source[Range::<usize>::from(span)])
+            .collect()
+    }
+
+    fn starts(spans: &[TextRange]) -> Vec<usize> {
+        spans.iter().map(|span| span.start().into()).collect()
+    }
+
+    #[test]
+    fn aliases() {
+        let parse = crate::parse(SHEET);
+        let sheet = crate::analyze(&parse);
+        let fruits = after(SHEET, "B1 = enum $fruits");
+        assert_eq!(symbol_at(&parse, &sheet, fruits), Some(Symbol::Alias(0)));
+        let defined = definition(&parse, &sheet, fruits).unwrap();
+        assert_eq!(usize::from(defined.start()), SHEET.find("fruits").unwrap());
+
+        let mentions = references(&parse, &sheet, fruits);
+        assert_eq!(texts(SHEET, &mentions), ["fruits"; 3]);
+        assert_eq!(mentions[0], defined);
+        assert_eq!(
+            starts(&mentions[1..]),
+            [
+                SHEET.find("$fruits").unwrap() + 1,
+                SHEET.rfind("$fruits").unwrap() + 1
+            ]
+        );
+
+        // each use refers to the latest definition before it
+        let source = "alias x = A1\nB1 = $x\nalias x = A2\nB2 = $x\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let mentions = references(&parse, &sheet, after(source, "B1 = $x"));
+        assert_eq!(starts(&mentions), [6, 19]);
+    }
+
+    #[test]
+    fn cells() {
+        let parse = crate::parse(SHEET);
+        let sheet = crate::analyze(&parse);
+        let a2 = "A2".parse().unwrap();
+        let in_range = after(SHEET, "alias fruits = A1:A2");
+        assert_eq!(symbol_at(&parse, &sheet, in_range), Some(Symbol::Cell(a2)));
+        // the last assignment wins
+        let defined = definition(&parse, &sheet, in_range).unwrap();
+        assert_eq!(usize::from(defined.start()), SHEET.rfind("A2").unwrap());
+        assert_eq!(
+            starts(&writes(&sheet, a2)),
+            [SHEET.find("A2").unwrap(), SHEET.rfind("A2").unwrap()]
+        );
+
+        let mentions = references(&parse, &sheet, after(SHEET, "A1"));
+        assert_eq!(texts(SHEET, &mentions), ["A1", "A1:A2"]);
+        assert_eq!(symbol_at(&parse, &sheet, 3.into()), None);
+    }
+
+    #[test]
+    fn rename_alias() {
+        let parse = crate::parse(SHEET);
+        let sheet = crate::analyze(&parse);
+        let fruits = after(SHEET, "alias fruits");
+        let edits = rename(&parse, &sheet, fruits, "produce").unwrap();
+        assert_eq!(edits.len(), 3);
+        assert_eq!(
+            TextEdit::apply(SHEET, &edits).unwrap(),
+            SHEET.replace("fruits", "produce")
+        );
+
+        let taken = rename(&parse, &sheet, fruits, "best");
+        assert!(matches!(taken, Err(RenameError::Taken { .. })), "{taken:?}");
+        assert_eq!(
+            rename(&parse, &sheet, fruits, "two words"),
+            Err(RenameError::InvalidName("two words".to_owned()))
+        );
+        assert_eq!(
+            rename(&parse, &sheet, 3.into(), "x"),
+            Err(RenameError::NothingToRename)
+        );
+    }
+
+    #[test]
+    fn rename_cell() {
+        let source = "A1 = 1\nalias one = A1\nB1 = A1\nC1 = $one\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let edits = rename(&parse, &sheet, after(source, "B1 = A1"), "D4").unwrap();
+        assert_eq!(
+            TextEdit::apply(source, &edits).unwrap(),
+            "D4 = 1\nalias one = D4\nB1 = D4\nC1 = $one\n"
+        );
+        let taken = rename(&parse, &sheet, 0.into(), "B1");
+        assert!(matches!(taken, Err(RenameError::Taken { .. })), "{taken:?}");
+        assert_eq!(
+            rename(&parse, &sheet, 0.into(), "$one"),
+            Err(RenameError::InvalidName("$one".to_owned()))
+        );
+
+        let source = "A1 = 1\nB1:B2 = A1:A2\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        assert_eq!(
+            rename(&parse, &sheet, 0.into(), "D4"),
+            Err(RenameError::InRange(TextRange::new(15.into(), 20.into())))
+        );
+    }
+}
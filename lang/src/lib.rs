@@ -1,3 +1,15 @@
+pub mod canonical;
+pub mod cell_index;
+pub mod diagnostic;
+pub mod edit;
+pub mod eval;
+pub mod fix;
+pub mod fmt;
 mod grammar;
+pub mod ide;
+pub mod line_index;
+pub mod lint;
 mod parser;
-pub use parser::parse;
+pub mod sema;
+pub use parser::{parse, Parse, TextEdit, TextEditError};
+pub use sema::{analyze, replay};
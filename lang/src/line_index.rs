@@ -0,0 +1,254 @@
+//! Conversions between byte offsets and line/column positions.
+//!
+//! cstree and chumsky count in bytes, people count in lines and characters, and
+//! the Language Server Protocol counts in UTF-16 code units by default. A
+//! [`LineIndex`] is built once per text and converts between all of them.
+//!
+//! Lines end at `\n`. A `\r` right before it is part of the line terminator, not
+//! of the line, so a file with `\r\n` line endings has the same columns as with
+//! `\n`.
+
+use std::collections::HashMap;
+
+use cstree::text::{TextRange, TextSize};
+
+/// A zero-based line and column, with the column counted in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// How a [`WideLineCol`] counts columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WideEncoding {
+    /// In UTF-16 code units, like LSP and JavaScript.
+    Utf16,
+    /// In characters (Unicode code points), like SARIF and most editors' status bars.
+    Utf32,
+}
+
+/// A zero-based line and column, with the column counted in the units of some
+/// [`WideEncoding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WideLineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+/// A character that takes more than one byte in UTF-8, by its columns in its line.
+#[derive(Debug, Clone, Copy)]
+struct WideChar {
+    start: u32,
+    end: u32,
+}
+
+impl WideChar {
+    fn len(self) -> u32 {
+        self.end - self.start
+    }
+
+    fn wide_len(self, encoding: WideEncoding) -> u32 {
+        match encoding {
+            // only characters outside the BMP, which take 4 bytes, need a surrogate pair
+            WideEncoding::Utf16 if self.len() == 4 => 2,
+            WideEncoding::Utf16 | WideEncoding::Utf32 => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    /// The offset at which each line starts. The first line always starts at 0,
+    /// and a text ending with a newline has an empty last line.
+    starts: Vec<TextSize>,
+    /// The offset at which each line ends, before its terminator.
+    ends: Vec<TextSize>,
+    /// The multi-byte characters of each line that has any, in order.
+    wide_chars: HashMap<u32, Vec<WideChar>>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut index = LineIndex {
+            starts: vec![TextSize::from(0)],
+            ends: Vec::new(),
+            wide_chars: HashMap::new(),
+        };
+        let mut line = 0;
+        let mut start = 0;
+        for (offset, c) in text.char_indices() {
+            if c == '\n' {
+                let end = if text[..offset].ends_with('\r') {
+                    offset - 1
+                } else {
+                    offset
+                };
+                index.ends.push(TextSize::from(end as u32));
+                index.starts.push(TextSize::from(offset as u32 + 1));
+                line += 1;
+                start = offset + 1;
+            } else if c.len_utf8() > 1 {
+                let col = (offset - start) as u32;
+                index.wide_chars.entry(line).or_default().push(WideChar {
+                    start: col,
+                    end: col + c.len_utf8() as u32,
+                });
+            }
+        }
+        index.ends.push(TextSize::from(text.len() as u32));
+        index
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The length of the indexed text.
+    pub fn len(&self) -> TextSize {
+        *self.ends.last().unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == TextSize::from(0)
+    }
+
+    /// The range of a line, without its terminator.
+    pub fn line_range(&self, line: u32) -> Option<TextRange> {
+        let line = line as usize;
+        Some(TextRange::new(*self.starts.get(line)?, self.ends[line]))
+    }
+
+    /// The line and column of an offset. Offsets past the end of the text are
+    /// treated as the end of the text.
+    pub fn line_col(&self, offset: TextSize) -> LineCol {
+        let offset = offset.min(self.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        LineCol {
+            line: line as u32,
+            col: u32::from(offset - self.starts[line]),
+        }
+    }
+
+    /// The offset of a line and column, or `None` if the line doesn't exist or the
+    /// column is past its end.
+    pub fn offset(&self, line_col: LineCol) -> Option<TextSize> {
+        let range = self.line_range(line_col.line)?;
+        let offset = range.start() + TextSize::from(line_col.col);
+        (offset <= range.end()).then_some(offset)
+    }
+
+    /// Count the column of `line_col` in `encoding` rather than in bytes.
+    pub fn to_wide(&self, encoding: WideEncoding, line_col: LineCol) -> WideLineCol {
+        let mut col = line_col.col;
+        for c in self.wide_chars(line_col.line) {
+            if c.end > line_col.col {
+                break;
+            }
+            col -= c.len() - c.wide_len(encoding);
+        }
+        WideLineCol {
+            line: line_col.line,
+            col,
+        }
+    }
+
+    /// Count the column of `line_col` in bytes rather than in `encoding`. This is
+    /// the inverse of [`LineIndex::to_wide`]. A column in the middle of a
+    /// character, like between the two halves of a UTF-16 surrogate pair, is
+    /// rounded down to the start of the character.
+    pub fn to_utf8(&self, encoding: WideEncoding, line_col: WideLineCol) -> LineCol {
+        let mut col = line_col.col;
+        for c in self.wide_chars(line_col.line) {
+            if c.start >= col {
+                break;
+            }
+            if col < c.start + c.wide_len(encoding) {
+                col = c.start;
+                break;
+            }
+            col += c.len() - c.wide_len(encoding);
+        }
+        LineCol {
+            line: line_col.line,
+            col,
+        }
+    }
+
+    fn wide_chars(&self, line: u32) -> impl Iterator<Item = WideChar> + '_ {
+        self.wide_chars.get(&line).into_iter().flatten().copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line_col(line: u32, col: u32) -> LineCol {
+        LineCol { line, col }
+    }
+
+    #[test]
+    fn lines() {
+        let index = LineIndex::new("A1 = 1\nB1 = 2\n\nC1 = 3");
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line_col(0.into()), line_col(0, 0));
+        assert_eq!(index.line_col(7.into()), line_col(1, 0));
+        assert_eq!(index.line_col(14.into()), line_col(2, 0));
+        assert_eq!(index.line_col(20.into()), line_col(3, 5));
+        assert_eq!(index.line_col(100.into()), line_col(3, 6));
+        assert_eq!(index.offset(line_col(1, 3)), Some(10.into()));
+        assert_eq!(index.offset(line_col(2, 1)), None);
+        assert_eq!(index.offset(line_col(4, 0)), None);
+
+        let index = LineIndex::new("A1 = 1\n");
+        assert_eq!(index.line_count(), 2);
+        assert_eq!(index.line_col(7.into()), line_col(1, 0));
+        assert!(LineIndex::new("").is_empty());
+    }
+
+    #[test]
+    fn crlf() {
+        let index = LineIndex::new("A1 = 1\r\nB1 = 2\r\n");
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(
+            index.line_range(0),
+            Some(TextRange::new(0.into(), 6.into()))
+        );
+        assert_eq!(index.line_col(8.into()), line_col(1, 0));
+        assert_eq!(index.offset(line_col(1, 6)), Some(14.into()));
+        assert_eq!(index.offset(line_col(1, 7)), None);
+    }
+
+    #[test]
+    fn wide() {
+        // `é` is 2 bytes and 1 UTF-16 unit, `𝄞` is 4 bytes and 2 UTF-16 units
+        let index = LineIndex::new("A1 = 1\nB1 = \"é𝄞x\"\n");
+        let x = line_col(1, 12);
+        assert_eq!(
+            index.to_wide(WideEncoding::Utf16, x),
+            WideLineCol { line: 1, col: 9 }
+        );
+        assert_eq!(
+            index.to_wide(WideEncoding::Utf32, x),
+            WideLineCol { line: 1, col: 8 }
+        );
+        for encoding in [WideEncoding::Utf16, WideEncoding::Utf32] {
+            for col in [0, 6, 8, 12, 14] {
+                let line_col = line_col(1, col);
+                let wide = index.to_wide(encoding, line_col);
+                assert_eq!(index.to_utf8(encoding, wide), line_col);
+            }
+        }
+        // the middle of `𝄞` rounds down to its start
+        assert_eq!(
+            index.to_utf8(WideEncoding::Utf16, WideLineCol { line: 1, col: 7 }),
+            line_col(1, 8)
+        );
+        // other lines are unaffected
+        assert_eq!(
+            index.to_wide(WideEncoding::Utf16, line_col(0, 5)),
+            WideLineCol { line: 0, col: 5 }
+        );
+    }
+}
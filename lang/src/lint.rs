@@ -0,0 +1,590 @@
+//! Lints: warnings about sheets that are valid, but probably not what was meant.
+//!
+//! Every lint has a [`Level`]. The defaults can be changed for a whole project in
+//! its [`CONFIG_FILE`]:
+//!
+//! ```toml
+//! [lints]
+//! unused-alias = "allow"
+//! hard-coded-number = "warn"
+//! ```
+//!
+//! and for a single file with a directive comment anywhere in it, which takes
+//! precedence over the project file:
+//!
+//! ```text
+//! //@ allow(unused-alias, empty-enum)
+//! //@ deny(shadowed-assignment)
+//! ```
+//!
+//! Other `//@` comments, like the `//@ exit-status: 1` in our ui tests, are
+//! ignored.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use cstree::text::{TextRange, TextSize};
+
+use crate::cell_index::{CellRef, RangeRef};
+use crate::diagnostic::{Code, Diagnostic, Label, Severity};
+use crate::eval::{Evaluated, Evaluator};
+use crate::parser::{Parse, SyntaxKind::*, SyntaxNode, SyntaxToken};
+use crate::sema::{self, Issue, Sheet, Value};
+
+/// The name of the project file, looked up in the directory of a sheet and its
+/// ancestors.
+pub const CONFIG_FILE: &str = "ssl.toml";
+
+/// The project file that applies to the sheet at `path`: the closest
+/// [`CONFIG_FILE`] in its directory or above it.
+pub fn config_file(path: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    path.ancestors()
+        .skip(1)
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|candidate| candidate.is_file())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    /// An `alias` that no `$name` refers to.
+    UnusedAlias,
+    /// See [`Issue::Overwritten`].
+    ShadowedAssignment,
+    /// `enum A1:A3` where none of `A1:A3` is assigned.
+    EmptyEnum,
+    /// A column holding both numbers and strings, not counting its first row.
+    InconsistentColumnTypes,
+    /// A number in a column that is otherwise computed from other cells.
+    HardCodedNumber,
+    /// See [`Issue::EnumMemberCase`].
+    EnumMemberCase,
+    /// A `#REF!` left by [deleting](crate::edit::delete_rows) the cells a
+    /// statement referred to.
+    DeletedReference,
+}
+
+impl Lint {
+    pub const ALL: &'static [Lint] = &[
+        Lint::UnusedAlias,
+        Lint::ShadowedAssignment,
+        Lint::EmptyEnum,
+        Lint::InconsistentColumnTypes,
+        Lint::HardCodedNumber,
+        Lint::EnumMemberCase,
+        Lint::DeletedReference,
+    ];
+
+    pub fn code(self) -> Code {
+        match self {
+            Lint::UnusedAlias => Code::UnusedAlias,
+            Lint::ShadowedAssignment => Code::ShadowedAssignment,
+            Lint::EmptyEnum => Code::EmptyEnum,
+            Lint::InconsistentColumnTypes => Code::InconsistentColumnTypes,
+            Lint::HardCodedNumber => Code::HardCodedNumber,
+            Lint::EnumMemberCase => Code::EnumMemberCase,
+            Lint::DeletedReference => Code::DeletedReference,
+        }
+    }
+
+    /// The name used in directives and the project file, like `unused-alias`.
+    pub fn name(self) -> &'static str {
+        self.code().name()
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().copied().find(|lint| lint.name() == name)
+    }
+
+    /// The level of this lint when nothing configures it. The lints about columns
+    /// are heuristics that some sheets break on purpose, so they are opt-in. A
+    /// deleted reference means the sheet lost a value, which is an error.
+    pub fn default_level(self) -> Level {
+        match self {
+            Lint::UnusedAlias
+            | Lint::ShadowedAssignment
+            | Lint::EmptyEnum
+            | Lint::EnumMemberCase => Level::Warn,
+            Lint::InconsistentColumnTypes | Lint::HardCodedNumber => Level::Allow,
+            Lint::DeletedReference => Level::Deny,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Don't report it at all.
+    Allow,
+    /// Report it as a warning.
+    Warn,
+    /// Report it as an error, which makes `ssl check` fail.
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        })
+    }
+}
+
+/// The level of every lint, as set by a project file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Lints that aren't here have their default level.
+    levels: BTreeMap<Lint, Level>,
+}
+
+impl Config {
+    /// Read the contents of a [`CONFIG_FILE`].
+    #[cfg(feature = "cli")]
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|err: toml::de::Error| err.to_string())?;
+        let mut config = Config::default();
+        for (key, value) in table {
+            if key != "lints" {
+                return Err(format!("unknown key `{key}`"));
+            }
+            let Some(lints) = value.as_table() else {
+                return Err("`lints` must be a table".to_owned());
+            };
+            for (name, level) in lints {
+                let lint = Lint::from_name(name).ok_or_else(|| format!("unknown lint `{name}`"))?;
+                let level = level.as_str().and_then(Level::from_name).ok_or_else(|| {
+                    format!("the level of `{name}` must be \"allow\", \"warn\" or \"deny\"")
+                })?;
+                config.set(lint, level);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels
+            .get(&lint)
+            .copied()
+            .unwrap_or_else(|| lint.default_level())
+    }
+
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+}
+
+/// Run every lint that isn't allowed, either by `config` or by a directive in the
+/// file. Malformed directives are reported too.
+///
+/// Semantic issues that are lints, like [`Issue::Overwritten`], are reported
+/// here rather than with the other issues of `sheet`, which are all errors.
+pub fn lint(parse: &Parse, sheet: &Sheet, source: &str, config: &Config) -> Vec<Diagnostic> {
+    let root = parse.red_tree();
+    let mut cx = Linter {
+        parse,
+        sheet,
+        source,
+        config: config.clone(),
+        diagnostics: Vec::new(),
+    };
+    cx.directives(&root);
+    cx.unused_alias(&root);
+    cx.issues();
+    cx.empty_enum(&root);
+    cx.columns();
+    cx.deleted_reference(&root);
+    cx.diagnostics
+}
+
+struct Linter<'a> {
+    parse: &'a Parse,
+    sheet: &'a Sheet,
+    source: &'a str,
+    config: Config,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn emit(&mut self, lint: Lint, mut diagnostic: Diagnostic) {
+        diagnostic.severity = match self.config.level(lint) {
+            Level::Allow => return,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn text(&self, span: TextRange) -> &str {
+        &self.source[Range::<usize>::from(span)]
+    }
+
+    /// Apply the `//@ allow(...)`, `//@ warn(...)` and `//@ deny(...)` comments.
+    fn directives(&mut self, root: &SyntaxNode) {
+        let comments = root
+            .descendants_with_tokens()
+            .filter_map(|elem| elem.into_token())
+            .filter(|token| token.kind() == COMMENT);
+        for comment in comments {
+            let text = self.parse.text(comment);
+            let Some(directive) = text.trim().strip_prefix("//@") else {
+                continue;
+            };
+            let Some((level, names)) = directive.split_once('(') else {
+                continue;
+            };
+            // The offset of the names in the file, to point at unknown ones: after
+            // the indentation, `//@`, the level and `(`.
+            let indent = text.len() - text.trim_start().len();
+            let mut offset = usize::from(comment.text_range().start())
+                + indent
+                + "//@".len()
+                + level.len()
+                + "(".len();
+            let Some(level) = Level::from_name(level.trim()) else {
+                continue;
+            };
+            let Some(names) = names.trim_end().strip_suffix(')') else {
+                self.diagnostics.push(Diagnostic::warning(
+                    Code::UnknownLint,
+                    "malformed directive",
+                    Label::new(comment.text_range(), "expected `)` after the lints"),
+                ));
+                continue;
+            };
+            for name in names.split(',') {
+                let start = offset + (name.len() - name.trim_start().len());
+                offset += name.len() + 1;
+                let name = name.trim();
+                match Lint::from_name(name) {
+                    Some(lint) => self.config.set(lint, level),
+                    None => {
+                        let span = TextRange::at(
+                            TextSize::from(start as u32),
+                            TextSize::from(name.len() as u32),
+                        );
+                        self.diagnostics.push(Diagnostic::warning(
+                            Code::UnknownLint,
+                            format!("unknown lint `{name}`"),
+                            Label::new(span, "this directive has no effect"),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn unused_alias(&mut self, root: &SyntaxNode) {
+        let parse = self.parse;
+        let used: HashSet<&str> = root
+            .descendants()
+            .filter(|node| node.kind() == ALIAS_EXPR)
+            .filter_map(ident)
+            .map(|name| parse.text(name))
+            .collect();
+        for stmt in root.descendants().filter(|node| node.kind() == ALIAS_STMT) {
+            let Some(name) = ident(stmt) else {
+                continue;
+            };
+            let text = parse.text(name);
+            if used.contains(text) {
+                continue;
+            }
+            let diagnostic = Diagnostic::warning(
+                Code::UnusedAlias,
+                format!("alias `{text}` is never used"),
+                Label::new(name.text_range(), format!("no `${text}` refers to this")),
+            );
+            self.emit(Lint::UnusedAlias, diagnostic);
+        }
+    }
+
+    /// The semantic issues that are lints.
+    fn issues(&mut self) {
+        let sheet = self.sheet;
+        for issue in &sheet.issues {
+            let lint = match issue {
+                Issue::Overwritten { .. } => Lint::ShadowedAssignment,
+                Issue::EnumMemberCase { .. } => Lint::EnumMemberCase,
+                _ => continue,
+            };
+            self.emit(lint, issue.diagnostic(self.source));
+        }
+    }
+
+    fn empty_enum(&mut self, root: &SyntaxNode) {
+        // The options of each enum, by the span of the target it is assigned to.
+        let enums: HashMap<TextRange, RangeRef> = self
+            .sheet
+            .assignments
+            .iter()
+            .filter_map(|assignment| match assignment.value {
+                Value::Enum(range) => Some((assignment.span, range)),
+                _ => None,
+            })
+            .collect();
+        for expr in root.descendants().filter(|node| node.kind() == ENUM_EXPR) {
+            let Some(place) = expr.first_child() else {
+                continue;
+            };
+            // The assignment this enum is the value of.
+            let assign = expr.parent().and_then(|expr| expr.parent());
+            let range = assign
+                .and_then(|assign| assign.first_child())
+                .and_then(|target| enums.get(&sema::trimmed_range(target)));
+            let Some(&range) = range else {
+                continue;
+            };
+            if self.sheet.cells_in(range).next().is_some() {
+                continue;
+            }
+            let options = self.text(sema::trimmed_range(place)).to_owned();
+            let diagnostic = Diagnostic::warning(
+                Code::EmptyEnum,
+                "enum has no options",
+                Label::new(
+                    sema::trimmed_range(expr),
+                    format!("nothing assigns any cell of `{options}`"),
+                ),
+            );
+            self.emit(Lint::EmptyEnum, diagnostic);
+        }
+    }
+
+    /// The lints that compare the cells of a column. The first row of each column is
+    /// left out, since that is usually a header.
+    fn columns(&mut self) {
+        let mut columns: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        let sheet = self.sheet;
+        for (cell, assignment) in sheet.cells() {
+            if cell.row.index() > 0 {
+                columns
+                    .entry(cell.col)
+                    .or_default()
+                    .push((cell, assignment));
+            }
+        }
+        let mut evaluator = Evaluator::new(sheet);
+        for cells in columns.values() {
+            self.inconsistent_column_types(cells, &mut evaluator);
+            self.hard_coded_number(cells);
+        }
+    }
+
+    fn inconsistent_column_types(
+        &mut self,
+        cells: &[(CellRef, &sema::Assignment)],
+        evaluator: &mut Evaluator,
+    ) {
+        let mut kind = |cell| match evaluator.eval(cell) {
+            Ok(Some(Evaluated::Int(_))) => Some("a number"),
+            Ok(Some(Evaluated::Str(_))) => Some("a string"),
+            _ => None,
+        };
+        let mut typed = cells
+            .iter()
+            .filter_map(|&(cell, assignment)| Some((cell, assignment, kind(cell)?)));
+        let Some((first, first_assignment, expected)) = typed.next() else {
+            return;
+        };
+        let mut reported = HashSet::new();
+        let mismatched: Vec<_> = typed
+            .filter(|&(_, assignment, kind)| kind != expected && reported.insert(assignment.span))
+            .collect();
+        for (cell, assignment, kind) in mismatched {
+            let diagnostic = Diagnostic::warning(
+                Code::InconsistentColumnTypes,
+                format!("column `{}` holds both numbers and strings", cell.col),
+                Label::new(assignment.span, format!("`{cell}` holds {kind}")),
+            )
+            .with_label(Label::new(
+                first_assignment.span,
+                format!("but `{first}` holds {expected}"),
+            ));
+            self.emit(Lint::InconsistentColumnTypes, diagnostic);
+        }
+    }
+
+    fn hard_coded_number(&mut self, cells: &[(CellRef, &sema::Assignment)]) {
+        let computed = cells
+            .iter()
+            .filter(|(_, assignment)| matches!(assignment.value, Value::Ref(_)))
+            .count();
+        if computed * 2 <= cells.len() {
+            return;
+        }
+        let mut reported = HashSet::new();
+        for &(cell, assignment) in cells {
+            if !matches!(assignment.value, Value::Int(_)) || !reported.insert(assignment.span) {
+                continue;
+            }
+            let diagnostic = Diagnostic::warning(
+                Code::HardCodedNumber,
+                "hard-coded number in a computed column",
+                Label::new(
+                    assignment.span,
+                    format!("most of column `{}` reads other cells", cell.col),
+                ),
+            );
+            self.emit(Lint::HardCodedNumber, diagnostic);
+        }
+    }
+
+    fn deleted_reference(&mut self, root: &SyntaxNode) {
+        let deleted = root
+            .descendants_with_tokens()
+            .filter_map(|elem| elem.into_token())
+            .filter(|token| token.kind() == DELETED_REF);
+        for token in deleted {
+            let diagnostic = Diagnostic::error(
+                Code::DeletedReference,
+                "reference to deleted cells",
+                Label::new(
+                    token.text_range(),
+                    "the cells this referred to were deleted",
+                ),
+            )
+            .with_note("refer to other cells, or remove the statement");
+            self.emit(Lint::DeletedReference, diagnostic);
+        }
+    }
+}
+
+/// The name in an `alias` statement or an `$alias` expression.
+fn ident(node: &SyntaxNode) -> Option<&SyntaxToken> {
+    node.children_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .find(|token| token.kind() == IDENT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// The codes and severities reported for `source` with the default config.
+    fn lints(source: &str) -> Vec<(Code, Severity)> {
+        let parse = crate::parse(source);
+        assert!(parse.errors.is_empty(), "{:?}", parse.errors);
+        let sheet = crate::analyze(&parse);
+        lint(&parse, &sheet, source, &Config::default())
+            .into_iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.severity))
+            .collect()
+    }
+
+    #[test]
+    fn defaults() {
+        let source = "alias fruits = A1:A3\nB1 = enum A1:A3\nD1 = 0\nD1 = 1\n";
+        assert_eq!(
+            lints(source),
+            [
+                (Code::UnusedAlias, Severity::Warning),
+                (Code::ShadowedAssignment, Severity::Warning),
+                (Code::EmptyEnum, Severity::Warning),
+            ]
+        );
+        assert!(lints("A1 = 1\nalias a = A1\nB1 = enum $a\n").is_empty());
+    }
+
+    #[test]
+    fn directives() {
+        let source = "//@ allow(unused-alias, empty-enum)\n//@ deny(shadowed-assignment)\n\
+                      alias fruits = A1:A3\nB1 = enum A1:A3\nD1 = 0\nD1 = 1\n";
+        assert_eq!(lints(source), [(Code::ShadowedAssignment, Severity::Error)]);
+
+        let source = "//@ exit-status: 1\n//@ allow( unused_alias)\nA1 = 1\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let diagnostics = lint(&parse, &sheet, source, &Config::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, Code::UnknownLint);
+        assert_eq!(
+            &source[Range::<usize>::from(diagnostics[0].primary.span)],
+            "unused_alias"
+        );
+
+        let source = "//@ allow(unused-alias\nalias one = A1\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let diagnostics = lint(&parse, &sheet, source, &Config::default());
+        let codes: Vec<_> = diagnostics.iter().map(|d| (d.code, &*d.message)).collect();
+        assert_eq!(
+            codes,
+            [
+                (Code::UnknownLint, "malformed directive"),
+                (Code::UnusedAlias, "alias `one` is never used"),
+            ]
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let source = "//@ warn(inconsistent-column-types, hard-coded-number)\n\
+                      A1 = \"price\"\nA2 = 1\nA3 = \"two\"\n\
+                      C1 = \"total\"\nC2:C4 = B2:B4\nC3 = 7\nB2:B4 = 1\n";
+        assert_eq!(
+            lints(source),
+            [
+                (Code::InconsistentColumnTypes, Severity::Warning),
+                (Code::HardCodedNumber, Severity::Warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn enum_member_case() {
+        let source = "A1 = \"apple\"\nB1 = enum A1\nB1 = \"Apple\"\n";
+        assert_eq!(lints(source), [(Code::EnumMemberCase, Severity::Warning)]);
+        assert!(lints("A1 = \"apple\"\nB1 = enum A1\nB1 = \"apple\"\n").is_empty());
+        assert!(lints(&format!("//@ allow(enum-member-case)\n{source}"))
+            .iter()
+            .all(|&(code, _)| code != Code::EnumMemberCase));
+    }
+
+    #[test]
+    fn deleted_reference() {
+        let source = "A1 = 1\nB1 = #REF!\n// #REF! in prose\nalias x = #REF!\n";
+        let parse = crate::parse(source);
+        let sheet = crate::analyze(&parse);
+        let diagnostics = lint(&parse, &sheet, source, &Config::default());
+        let spans: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.code == Code::DeletedReference)
+            .map(|diagnostic| {
+                (
+                    Range::<usize>::from(diagnostic.primary.span),
+                    diagnostic.severity,
+                )
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [(12..17, Severity::Error), (46..51, Severity::Error)]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn config() {
+        let config = Config::from_toml("[lints]\nunused-alias = \"deny\"\n").unwrap();
+        assert_eq!(config.level(Lint::UnusedAlias), Level::Deny);
+        assert_eq!(config.level(Lint::HardCodedNumber), Level::Allow);
+        assert!(Config::from_toml("[lints]\nunused = \"deny\"\n").is_err());
+        assert!(Config::from_toml("[lints]\nunused-alias = \"off\"\n").is_err());
+    }
+}
@@ -20,6 +20,8 @@ mod syntax {
         // symbols
         NEWLINE = 0,
         CELL,
+        /// `#REF!`, in place of a reference to cells that were deleted
+        DELETED_REF,
         EQ,
         INT,
         COLON,
@@ -36,6 +38,8 @@ mod syntax {
         COMMENT,
         IDENT,
         STR,
+        /// text that doesn't parse
+        ERROR_TOK,
 
         // composite nodes
 
@@ -50,6 +54,8 @@ mod syntax {
         ASSIGN,
         ALIAS_STMT,
         STATEMENT,
+        /// a line that should have been a statement, but failed to parse
+        ERROR,
         // ARRAY_RANGE,
 
         // this MUST come last in the enum; we depend on it for memory safety
@@ -78,7 +84,6 @@ mod syntax {
     }
 
     pub type SyntaxNode = cstree::syntax::SyntaxNode<SyntaxKind>;
-    #[allow(dead_code)]
     pub type SyntaxToken = cstree::syntax::SyntaxToken<SyntaxKind>;
     #[allow(dead_code)]
     pub type SyntaxElement = cstree::util::NodeOrToken<SyntaxNode, SyntaxToken>;
@@ -87,21 +92,27 @@ mod syntax {
 use std::{
     fmt,
     marker::PhantomData,
-    ops::{Deref, DerefMut},
+    ops::{Deref, DerefMut, Range},
 };
 
-use cstree::interning::TokenInterner;
+use cstree::interning::{new_threaded_interner, MultiThreadedTokenInterner};
 pub use syntax::*;
 
 use chumsky::{
+    error::RichPattern,
     extension::v1::{Ext, ExtParser},
     input::InputRef,
     prelude::*,
 };
-use cstree::build::GreenNodeBuilder;
-use cstree::green::GreenNode;
+use cstree::build::{GreenNodeBuilder, NodeCache};
+use cstree::green::{GreenNode, GreenToken};
+use cstree::text::{TextRange, TextSize};
+use cstree::util::NodeOrToken;
+
+use crate::diagnostic::{Code, Diagnostic, Label};
+use crate::line_index::LineIndex;
 
-pub(crate) type CSTError<'a> = Simple<'a, char>;
+pub(crate) type CSTError<'a> = Rich<'a, char>;
 pub(crate) type CSTExtra<'a> = extra::Full<CSTError<'a>, RowanRecorder<'a>, ()>;
 pub(crate) trait CSTParser<'a, O = ()>:
     chumsky::Parser<'a, &'a str, O, CSTExtra<'a>>
@@ -109,14 +120,20 @@ pub(crate) trait CSTParser<'a, O = ()>:
 }
 impl<'a, O, T> CSTParser<'a, O> for T where T: chumsky::Parser<'a, &'a str, O, CSTExtra<'a>> {}
 
+/// Interns the text of tokens. It can be shared between threads, so that a
+/// [`Parse`] can be sent to and read from any thread.
+pub(crate) type Interner = MultiThreadedTokenInterner;
+
+type Builder<'a> = GreenNodeBuilder<'a, 'static, SyntaxKind, Interner>;
+
 pub(crate) struct RowanRecorder<'a> {
-    builder: GreenNodeBuilder<'a, 'static, SyntaxKind>,
+    builder: Builder<'a>,
 }
 
 impl<'a> Default for RowanRecorder<'a> {
     fn default() -> Self {
         Self {
-            builder: GreenNodeBuilder::new(),
+            builder: GreenNodeBuilder::from_interner(new_threaded_interner()),
         }
     }
 }
@@ -139,7 +156,7 @@ impl<'a> chumsky::recorder::Recorder<'a, &'a str> for RowanRecorder<'a> {
 }
 
 impl<'a> Deref for RowanRecorder<'a> {
-    type Target = GreenNodeBuilder<'a, 'static, SyntaxKind>;
+    type Target = Builder<'a>;
 
     fn deref(&self) -> &Self::Target {
         &self.builder
@@ -152,13 +169,17 @@ impl<'a> DerefMut for RowanRecorder<'a> {
     }
 }
 
-pub struct Parse<'a> {
+/// The result of parsing a file. It owns its text and is `Send + Sync`, so it can
+/// be parsed on one thread and used on others.
+pub struct Parse {
     pub root: GreenNode,
-    interner: TokenInterner,
-    pub errors: Vec<CSTError<'a>>,
+    interner: Interner,
+    /// The text that was parsed, kept to apply edits to it.
+    text: String,
+    pub errors: Vec<Diagnostic>,
 }
 
-impl<'a> fmt::Debug for Parse<'a> {
+impl fmt::Debug for Parse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if !self.errors.is_empty() {
             writeln!(f, "error: {:?}", self.errors)?;
@@ -167,7 +188,7 @@ impl<'a> fmt::Debug for Parse<'a> {
     }
 }
 
-impl Parse<'_> {
+impl Parse {
     /// Return a red tree based on the green tree we parsed, ignoring errors.
     ///
     /// Unlike a green tree, this has parent pointers, offsets, and identity semantics.
@@ -179,27 +200,327 @@ impl Parse<'_> {
     pub fn red_tree(&self) -> SyntaxNode {
         SyntaxNode::new_root(self.root.clone())
     }
+
+    /// The source text of a token in this tree.
+    pub fn text(&self, token: &SyntaxToken) -> &str {
+        token.resolve_text(&self.interner)
+    }
+
+    /// The text that was parsed.
+    pub fn source(&self) -> &str {
+        &self.text
+    }
+
+    /// Apply `edit` to the text that was parsed, and parse the result. If the span
+    /// of `edit` isn't in the text, nothing changes.
+    ///
+    /// Every statement, comment and blank line is a child of the root that ends
+    /// with its line, so only the children the edit touches are parsed again. The
+    /// green nodes of all others are reused as they are, and so are their errors.
+    pub fn reparse(&mut self, edit: &TextEdit) -> Result<(), TextEditError> {
+        let range = edit.range_in(&self.text)?;
+        self.text.replace_range(range, &edit.replacement);
+        // where an offset at or after the end of the edit moves to
+        let moved = |offset: TextSize| {
+            TextSize::from(
+                (usize::from(offset) - usize::from(edit.span.len()) + edit.replacement.len())
+                    as u32,
+            )
+        };
+
+        let children: Vec<TextRange> = self
+            .red_tree()
+            .children_with_tokens()
+            .map(|elem| match elem {
+                NodeOrToken::Node(node) => node.text_range(),
+                NodeOrToken::Token(token) => token.text_range(),
+            })
+            .collect();
+        // Include the child that ends where the edit starts, in case the edit
+        // joins it with the next line.
+        let Some(mut first) = children
+            .iter()
+            .position(|child| child.end() >= edit.span.start())
+        else {
+            *self = parse(&self.text);
+            return Ok(());
+        };
+        let text = &self.text;
+        // The region must start with a line, so that its errors are reported like
+        // in the whole text. The text before the edit didn't change.
+        while first > 0 && !text[..usize::from(children[first].start())].ends_with('\n') {
+            first -= 1;
+        }
+        let mut last = children
+            .iter()
+            .rposition(|child| child.start() <= edit.span.end())
+            .unwrap_or(first);
+        // It must also end with a line of the new text, so that what follows
+        // parses the same as before.
+        while last + 1 < children.len()
+            && !text[..usize::from(moved(children[last].end()))].ends_with('\n')
+        {
+            last += 1;
+        }
+        let start = usize::from(children[first].start());
+        let end = children[last].end();
+        let region = &text[start..usize::from(moved(end))];
+
+        let interner = std::mem::replace(&mut self.interner, new_threaded_interner());
+        let builder = GreenNodeBuilder::from_cache(NodeCache::from_interner(interner));
+        let reparsed = parse_with(builder, region, start);
+        // A string can span lines. One that isn't closed in the region might be
+        // closed after it, which changes how everything in between parses.
+        if !reparsed.errors.is_empty() && region.contains('"') {
+            *self = parse(&self.text);
+            return Ok(());
+        }
+
+        let owned = |elem: NodeOrToken<&GreenNode, &GreenToken>| match elem {
+            NodeOrToken::Node(node) => NodeOrToken::Node(node.clone()),
+            NodeOrToken::Token(token) => NodeOrToken::Token(token.clone()),
+        };
+        let green: Vec<NodeOrToken<GreenNode, GreenToken>> = self
+            .root
+            .children()
+            .take(first)
+            .chain(reparsed.root.children())
+            .chain(self.root.children().skip(last + 1))
+            .map(owned)
+            .collect();
+        self.root = GreenNode::new(SyntaxKind::ROOT.into(), green);
+        self.interner = reparsed.interner;
+
+        let at_end = last + 1 == children.len();
+        let (before, after): (Vec<_>, Vec<_>) = std::mem::take(&mut self.errors)
+            .into_iter()
+            .filter(|err| {
+                let offset = err.primary.span.start();
+                usize::from(offset) < start || (offset >= end && !at_end)
+            })
+            .partition(|err| usize::from(err.primary.span.start()) < start);
+        let after = after.into_iter().map(|err| {
+            err.map_spans(|span| TextRange::new(moved(span.start()), moved(span.end())))
+        });
+        self.errors = before
+            .into_iter()
+            .chain(reparsed.errors)
+            .chain(after)
+            .collect();
+        Ok(())
+    }
+}
+
+/// A change to a text: `span` is replaced with `replacement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub span: TextRange,
+    pub replacement: String,
+}
+
+/// Why edits can't be applied to a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextEditError {
+    /// The span ends after the text, or starts or ends inside a character.
+    OutOfBounds(TextRange),
+    /// Both spans replace some of the same text, or something is inserted where the
+    /// other one ends, so the result would depend on the order of the edits.
+    Overlap(TextRange, TextRange),
+}
+
+impl fmt::Display for TextEditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextEditError::OutOfBounds(span) => write!(f, "{span:?} is not in the text"),
+            TextEditError::Overlap(a, b) => write!(f, "the edits of {a:?} and {b:?} overlap"),
+        }
+    }
+}
+
+impl std::error::Error for TextEditError {}
+
+impl TextEdit {
+    /// Apply `edits`, whose spans are all relative to `text`, in any order.
+    pub fn apply(text: &str, edits: &[TextEdit]) -> Result<String, TextEditError> {
+        let mut edits: Vec<_> = edits.iter().collect();
+        edits.sort_by_key(|edit| (edit.span.start(), edit.span.end()));
+        let mut output = String::with_capacity(text.len());
+        // The end of the last replaced span, up to which `text` was copied.
+        let mut copied = 0;
+        let mut last: Option<TextRange> = None;
+        for edit in edits {
+            let range = edit.range_in(text)?;
+            if let Some(last) = last.filter(|&last| overlaps(last, edit.span)) {
+                return Err(TextEditError::Overlap(last, edit.span));
+            }
+            output.push_str(&text[copied..range.start]);
+            output.push_str(&edit.replacement);
+            copied = range.end;
+            last = Some(edit.span);
+        }
+        output.push_str(&text[copied..]);
+        Ok(output)
+    }
+
+    /// The span as a range of `text`, if it is one.
+    fn range_in(&self, text: &str) -> Result<Range<usize>, TextEditError> {
+        let range = Range::<usize>::from(self.span);
+        if range.end <= text.len()
+            && text.is_char_boundary(range.start)
+            && text.is_char_boundary(range.end)
+        {
+            Ok(range)
+        } else {
+            Err(TextEditError::OutOfBounds(self.span))
+        }
+    }
+}
+
+/// Whether the edits of `first` and `second`, which doesn't start before it, can't
+/// both be applied.
+pub(crate) fn overlaps(first: TextRange, second: TextRange) -> bool {
+    second.start() < first.end() || (second.is_empty() && second.start() == first.end())
 }
 
 pub fn parse(text: &str) -> Parse {
-    let mut builder = RowanRecorder {
-        builder: GreenNodeBuilder::new(),
-    };
+    let builder = GreenNodeBuilder::from_interner(new_threaded_interner());
+    parse_with(builder, text, 0)
+}
+
+/// Parse `text`, which starts with a line, and report errors as if it started at
+/// `offset`.
+fn parse_with<'a>(builder: Builder<'a>, text: &'a str, offset: usize) -> Parse {
+    let mut builder = RowanRecorder { builder };
     // we don't put this in parser() to ensure rowan never panics even on horribly invalid programs
     builder.start_node(SyntaxKind::ROOT.into());
+    let index = LineIndex::new(text);
+    let shift = |span: TextRange| span + TextSize::from(offset as u32);
     let errors = crate::grammar::parser()
         .parse_with_state(text, &mut builder)
-        .into_errors();
+        .into_errors()
+        .iter()
+        .map(|err| syntax_error(err, text, &index).map_spans(&shift))
+        .collect();
     builder.finish_node();
     let (root, interner) = builder.builder.finish();
     // dbg!(&errors, &builder);
     Parse {
         root,
         interner: interner.unwrap().into_interner().unwrap(),
+        text: text.to_owned(),
         errors,
     }
 }
 
+/// The order in which we list what a syntax error expected, roughly the order in
+/// which it would appear in a statement. Unknown patterns go last.
+const EXPECTED_ORDER: &[&str] = &[
+    "a cell",
+    "`$alias`",
+    "`enum`",
+    "a number",
+    "a string",
+    "`alias`",
+    "a name",
+    "`=`",
+    "`:`",
+    "a row number",
+    "a comment",
+    "end of line",
+    "end of file",
+];
+
+/// Turn a chumsky error into a diagnostic saying what we expected, and after what.
+fn syntax_error(err: &CSTError, source: &str, index: &LineIndex) -> Diagnostic {
+    let range = err.span().into_range();
+    let line = index.line_col(TextSize::from(range.start as u32)).line;
+    let line = index.line_range(line).unwrap();
+    let (line_start, line_end) = (usize::from(line.start()), usize::from(line.end()));
+    let before = &source[line_start..range.start];
+    let indent = before.len() - before.trim_start().len();
+
+    // A word followed by another one, like `type C1 = int`, is more likely a
+    // statement we don't know than a cell missing its row number.
+    let word = before.trim_start();
+    let next = source[range.start..line_end].trim_start_matches([' ', '\t']);
+    if matches!(err.found(), Some(' ' | '\t'))
+        && !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphabetic())
+        && next.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '$')
+    {
+        return Diagnostic::error(
+            Code::SyntaxError,
+            format!("unknown statement `{word}`"),
+            Label::new(
+                text_range(line_start + indent..range.start),
+                "expected a cell or `alias`",
+            ),
+        );
+    }
+
+    let found = match err.found() {
+        Some('\n' | '\r') => "end of line".to_owned(),
+        Some(c) if c.is_whitespace() => "whitespace".to_owned(),
+        Some(c) => format!("`{c}`"),
+        None => "end of file".to_owned(),
+    };
+    let mut expected: Vec<String> = Vec::new();
+    for pattern in err.expected() {
+        let pattern = match pattern {
+            RichPattern::Token(c) => format!("`{}`", &**c),
+            RichPattern::Label(label) => label.to_string(),
+            RichPattern::EndOfInput => "end of file".to_owned(),
+            // "anything else" tells the user nothing
+            #[allow(unreachable_patterns)]
+            _ => continue,
+        };
+        if !expected.contains(&pattern) {
+            expected.push(pattern);
+        }
+    }
+    expected.sort_by_key(|pattern| {
+        EXPECTED_ORDER
+            .iter()
+            .position(|known| known == pattern)
+            .unwrap_or(EXPECTED_ORDER.len())
+    });
+
+    let mut message = match expected.split_last() {
+        None => format!("unexpected {found}"),
+        Some((last, [])) => format!("expected {last}"),
+        Some((last, rest)) => format!("expected {} or {last}", rest.join(", ")),
+    };
+    if let (false, Some(previous)) = (expected.is_empty(), last_token(before)) {
+        message.push_str(&format!(" after `{previous}`"));
+    }
+    let primary = Label::new(text_range(range), format!("unexpected {found}"));
+    let mut diagnostic = Diagnostic::error(Code::SyntaxError, message, primary);
+    if !before.trim().is_empty() {
+        let statement = text_range(line_start + indent..line_end);
+        diagnostic = diagnostic.with_label(Label::new(statement, "in this statement"));
+    }
+    diagnostic
+}
+
+/// Roughly the last token in `line`: a word, a string or a single symbol.
+fn last_token(line: &str) -> Option<&str> {
+    let line = line.trim_end();
+    let last = line.chars().next_back()?;
+    let start = if last.is_alphanumeric() || last == '_' {
+        line.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+            .len()
+    } else if last == '"' {
+        line[..line.len() - 1].rfind('"').unwrap_or(line.len() - 1)
+    } else {
+        line.len() - last.len_utf8()
+    };
+    Some(&line[start..])
+}
+
+fn text_range(range: std::ops::Range<usize>) -> TextRange {
+    TextRange::new((range.start as u32).into(), (range.end as u32).into())
+}
+
 pub(crate) struct RowanNode_<'a, O, P: CSTParser<'a, O>> {
     parser: P,
     kind: SyntaxKind,
@@ -306,3 +627,122 @@ pub(crate) fn rowan_leaf<'a, O, P: CSTParser<'a, O>>(
         _marker: PhantomData,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Check that reparsing `source` after replacing `span` with `replacement`
+    /// gives the same tree and errors as parsing the new text from scratch.
+    fn check_reparse(source: &str, span: Range<usize>, replacement: &str) {
+        let edit = TextEdit {
+            span: text_range(span.clone()),
+            replacement: replacement.to_owned(),
+        };
+        let mut text = source.to_owned();
+        text.replace_range(span, replacement);
+        let mut reparsed = parse(source);
+        reparsed.reparse(&edit).unwrap();
+        assert_eq!(reparsed.source(), text);
+        assert_eq!(
+            format!("{reparsed:?}"),
+            format!("{:?}", parse(&text)),
+            "{text:?}"
+        );
+    }
+
+    #[test]
+    fn reparse() {
+        let source = "A1 = 1\nA2 = 2\n// A3\n  \nalias x = A1\n";
+        // change a value, join two lines, insert a line
+        check_reparse(source, 5..6, "42");
+        check_reparse(source, 6..7, "");
+        check_reparse(source, 7..7, "B1 = 3\n");
+        // after a blank line, at the end, everything
+        check_reparse(source, 24..24, "// ");
+        check_reparse(source, source.len()..source.len(), "A3 = ");
+        check_reparse(source, 0..source.len(), "");
+        check_reparse("", 0..0, "A1 = 1");
+        // errors before and after the edit stay where they are
+        check_reparse("A1 = \nA2 = 2\nA3 3\n", 7..8, "1");
+        check_reparse("A1 = \nA2 = 2\nA3 3\n", 6..6, "B1 = 1\n");
+        // strings can span lines
+        check_reparse("A1 = \"a\nb\"\nA2 = 2\n", 5..6, "");
+        check_reparse("A1 = 1\nA2 = 2\nA3 = \"x\"\n", 5..5, "\"");
+        // errors in an indented line, or after a comment
+        check_reparse("A1 = 1\n  // one\n  A2 = 2\n", 21..22, "");
+        check_reparse("A1 = 1\n  // one\nA2 = 2\n", 10..11, "");
+
+        // an edit outside the text, or inside a character, changes nothing
+        let source = "A1 = \"é\"\n";
+        let mut parse = parse(source);
+        for span in [7..7, 5..20] {
+            let edit = TextEdit {
+                span: text_range(span.clone()),
+                replacement: String::new(),
+            };
+            let err = parse.reparse(&edit);
+            assert_eq!(err, Err(TextEditError::OutOfBounds(text_range(span))));
+            assert_eq!(parse.source(), source);
+        }
+    }
+
+    #[test]
+    fn apply_edits() {
+        let edit = |span: Range<usize>, replacement: &str| TextEdit {
+            span: text_range(span),
+            replacement: replacement.to_owned(),
+        };
+        let source = "A1 = 1\nA2 = \"é\"\n";
+        let edits = [edit(13, 15, "e"), edit(0, 2, "B1"), edit(7, 7, "// two\n")];
+        assert_eq!(
+            TextEdit::apply(source, &edits),
+            Ok("B1 = 1\n// two\nA2 = \"e\"\n".to_owned())
+        );
+        let overlap = [edit(0, 4, ""), edit(2, 6, "")];
+        assert_eq!(
+            TextEdit::apply(source, &overlap),
+            Err(TextEditError::Overlap(text_range(0..4), text_range(2..6)))
+        );
+        let inserts = [edit(7, 7, "a"), edit(7, 7, "b")];
+        assert!(TextEdit::apply(source, &inserts).is_err());
+        for span in [14..14, 10..30] {
+            assert_eq!(
+                TextEdit::apply(source, &[edit(span.clone(), "")]),
+                Err(TextEditError::OutOfBounds(text_range(span)))
+            );
+        }
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Parse>();
+
+        let source = "A1 = 1\nalias x = A1\n";
+        let parse = std::thread::spawn(move || parse(source)).join().unwrap();
+        let parse = std::sync::Arc::new(parse);
+        let shared = parse.clone();
+        let text = std::thread::spawn(move || shared.source().to_owned())
+            .join()
+            .unwrap();
+        assert_eq!(text, source);
+        assert_eq!(format!("{parse:?}"), format!("{:?}", super::parse(source)));
+    }
+
+    proptest! {
+        #[test]
+        fn reparse_like_parse(
+            source in "[A1 =$:a\"/\r\n]{0,40}",
+            start in 0..40usize,
+            len in 0..8usize,
+            replacement in "[A1 =\"/\n]{0,8}",
+        ) {
+            let start = start.min(source.len());
+            let end = (start + len).min(source.len());
+            check_reparse(&source, start..end, &replacement);
+        }
+    }
+}
@@ -0,0 +1,800 @@
+//! Semantic model of a sheet.
+//!
+//! The CST only knows about syntax. This module resolves aliases, expands ranges
+//! into the cells they cover and applies assignments in source order, so that we
+//! know which statement defines each cell. Ranges of more than
+//! [`MAX_RANGE_CELLS`] cells are rejected, and so is every statement after the
+//! first [`MAX_SHEET_CELLS`] cells were written or read, which bounds the time
+//! and memory an analysis takes.
+//!
+//! # Repeated assignments
+//!
+//! A cell may be assigned any number of times; the last assignment wins. Ranges
+//! follow the same rule cell by cell, so `A1:A3 = 0` followed by `A2 = 1` leaves
+//! `A1` and `A3` at `0`, and `A2 = 1` followed by `A1:A3 = 0` leaves all of them
+//! at `0`.
+//!
+//! Overwriting every cell of an assignment before any statement read one of them
+//! is almost always a mistake: the earlier assignment has no effect at all. We
+//! report that as [`Issue::Overwritten`], pointing at the previous assignment.
+//! Reading a cell in between (e.g. `E1 = D1` between `D1 = 0` and `D1 = 3`)
+//! marks the reassignment as intentional, and so does overwriting only some cells
+//! of a range, which is how defaults are set: `A1:A3 = 0` followed by `A2 = 1`.
+//! Since it isn't always a mistake, this is the `shadowed-assignment` lint, whose
+//! level is up to [`crate::lint`].
+//!
+//! A string assigned to a cell holding an enum picks one of its options: the
+//! cell keeps its enum as its type, so this doesn't overwrite the enum, while
+//! picking again does overwrite the previous pick. If the string isn't one of
+//! the options, but differs from one only in its casing, that is reported as
+//! [`Issue::EnumMemberCase`].
+//!
+//! Because of this, a file is effectively a journal of edits. [`replay`] stops the
+//! journal at a given line, and [`Sheet::history`] lists every value a cell held.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use cstree::{text::TextRange, util::NodeOrToken};
+
+use crate::cell_index::{CellRef, RangeRef};
+use crate::diagnostic::{Code, Diagnostic, Label};
+use crate::eval::{Evaluated, Evaluator};
+use crate::parser::{Parse, SyntaxKind::*, SyntaxNode, SyntaxToken};
+
+/// The largest number of cells a range can have.
+pub const MAX_RANGE_CELLS: u128 = 1 << 20;
+
+/// The largest number of cells the statements of a sheet can write and read in
+/// total, counting a cell once for every statement that uses it.
+pub const MAX_SHEET_CELLS: u128 = 1 << 22;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Str(String),
+    /// `enum A1:A3`: one of the values held by the given cells.
+    Enum(RangeRef),
+    /// `A1`, `A1:A3` or `$alias`: whatever the given cells hold.
+    Ref(RangeRef),
+}
+
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub target: RangeRef,
+    pub value: Value,
+    /// The place being assigned, e.g. `D1` in `D1 = 3`.
+    pub span: TextRange,
+}
+
+#[derive(Debug, Clone)]
+pub struct Alias {
+    pub name: String,
+    pub target: RangeRef,
+    /// The name in the `alias` statement.
+    pub span: TextRange,
+}
+
+/// A use of an alias, like `$fruits`.
+#[derive(Debug, Clone)]
+pub struct AliasRef {
+    pub name: String,
+    /// The name, without the `$`.
+    pub span: TextRange,
+    /// The index in [`Sheet::definitions`] of the `alias` statement this refers
+    /// to, or `None` if no alias of this name was defined before it.
+    pub definition: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    /// Every cell assigned at `previous` was assigned again before anything read
+    /// one of them.
+    Overwritten {
+        span: TextRange,
+        previous: TextRange,
+    },
+    UndefinedAlias {
+        name: String,
+        span: TextRange,
+        /// A defined alias with a similar name, which was probably meant instead.
+        similar: Option<String>,
+    },
+    /// A cell reference outside of the sheet, like `A0`.
+    InvalidCell {
+        span: TextRange,
+    },
+    IntOutOfRange {
+        span: TextRange,
+    },
+    /// A range of more than [`MAX_RANGE_CELLS`] cells.
+    RangeTooLarge {
+        span: TextRange,
+    },
+    /// A string assigned to an enum cell that is one of the enum's options, except
+    /// for its casing. `span` is the string literal.
+    EnumMemberCase {
+        span: TextRange,
+        option: String,
+    },
+    /// The first assignment that would take the sheet past [`MAX_SHEET_CELLS`].
+    /// It and every assignment after it are ignored.
+    SheetTooLarge {
+        span: TextRange,
+    },
+}
+
+impl Issue {
+    /// Whether this makes the sheet invalid, as opposed to merely suspicious.
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            Issue::Overwritten { .. } | Issue::EnumMemberCase { .. }
+        )
+    }
+
+    /// Describe this issue. `source` is the text of the parsed file, used to quote
+    /// the cells involved.
+    pub fn diagnostic(&self, source: &str) -> Diagnostic {
+        let text = |span: TextRange| &source[Range::<usize>::from(span)];
+        match self {
+            Issue::Overwritten { span, previous } => Diagnostic::warning(
+                Code::ShadowedAssignment,
+                format!("`{}` overwrites a value that was never read", text(*span)),
+                Label::new(*span, "reassigned here"),
+            )
+            .with_label(Label::new(
+                *previous,
+                format!("`{}` was previously assigned here", text(*previous)),
+            )),
+            Issue::UndefinedAlias {
+                name,
+                span,
+                similar,
+            } => {
+                let diagnostic = Diagnostic::error(
+                    Code::UndefinedAlias,
+                    format!("undefined alias `${name}`"),
+                    Label::new(*span, "no `alias` statement defines this name before here"),
+                );
+                match similar {
+                    Some(similar) => diagnostic.with_fix(
+                        "an alias with a similar name exists",
+                        *span,
+                        format!("${similar}"),
+                    ),
+                    None => diagnostic,
+                }
+            }
+            Issue::InvalidCell { span } => Diagnostic::error(
+                Code::InvalidCell,
+                "invalid cell",
+                Label::new(
+                    *span,
+                    format!("`{}` is not a cell in the sheet", text(*span)),
+                ),
+            ),
+            Issue::IntOutOfRange { span } => Diagnostic::error(
+                Code::IntOutOfRange,
+                "integer literal is too large",
+                Label::new(*span, format!("`{}` does not fit in 64 bits", text(*span))),
+            ),
+            Issue::RangeTooLarge { span } => Diagnostic::error(
+                Code::RangeTooLarge,
+                "range is too large",
+                Label::new(
+                    *span,
+                    format!("`{}` has more than {MAX_RANGE_CELLS} cells", text(*span)),
+                ),
+            ),
+            Issue::SheetTooLarge { span } => Diagnostic::error(
+                Code::SheetTooLarge,
+                "sheet is too large",
+                Label::new(
+                    *span,
+                    format!("this makes the sheet use more than {MAX_SHEET_CELLS} cells"),
+                ),
+            )
+            .with_note("this and every later assignment is ignored"),
+            Issue::EnumMemberCase { span, option } => Diagnostic::warning(
+                Code::EnumMemberCase,
+                format!("`{}` is not an option of the enum", text(*span)),
+                Label::new(
+                    *span,
+                    format!("the option `\"{option}\"` is spelled differently"),
+                ),
+            )
+            .with_fix(
+                "use the casing of the option",
+                *span,
+                format!("\"{option}\""),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Sheet {
+    /// Every assignment, in source order.
+    pub assignments: Vec<Assignment>,
+    /// Index into `assignments` of the assignment that last wrote each cell.
+    cells: BTreeMap<CellRef, usize>,
+    /// Every `alias` statement, in source order. The same name can be defined
+    /// again, and each use refers to the latest definition before it.
+    pub definitions: Vec<Alias>,
+    /// Every use of an alias, in source order.
+    pub references: Vec<AliasRef>,
+    /// Index into `definitions` of the latest definition of each name.
+    aliases: HashMap<String, usize>,
+    pub issues: Vec<Issue>,
+}
+
+impl Sheet {
+    /// The assignment that gives `cell` its final value, if any.
+    pub fn get(&self, cell: CellRef) -> Option<&Assignment> {
+        self.cells.get(&cell).map(|&i| &self.assignments[i])
+    }
+
+    /// Every cell that was assigned, column by column, with the assignment that
+    /// defines it.
+    pub fn cells(&self) -> impl Iterator<Item = (CellRef, &Assignment)> + '_ {
+        self.cells
+            .iter()
+            .map(|(&cell, &i)| (cell, &self.assignments[i]))
+    }
+
+    /// The cells of `range` that were assigned, column by column, with the
+    /// assignment that defines each. This skips the unassigned parts of `range`,
+    /// however large it is.
+    pub fn cells_in(&self, range: RangeRef) -> impl Iterator<Item = (CellRef, &Assignment)> + '_ {
+        let (top, bottom) = (range.start().row, range.end().row);
+        let mut next = Some(range.start());
+        std::iter::from_fn(move || loop {
+            let (&cell, &i) = self.cells.range(next?..=range.end()).next()?;
+            let next_col = || (cell.col + 1).map(|col| CellRef::new(col, top));
+            if cell.row < top {
+                next = Some(CellRef::new(cell.col, top));
+            } else if cell.row > bottom {
+                next = next_col();
+            } else {
+                next = if cell.row == bottom {
+                    next_col()
+                } else {
+                    (cell.row + 1).map(|row| CellRef::new(cell.col, row))
+                };
+                return Some((cell, &self.assignments[i]));
+            }
+        })
+    }
+
+    /// The latest definition of an alias.
+    pub fn alias(&self, name: &str) -> Option<&Alias> {
+        self.aliases.get(name).map(|&i| &self.definitions[i])
+    }
+
+    /// Every assignment that wrote `cell`, oldest first.
+    pub fn history(&self, cell: CellRef) -> impl Iterator<Item = &Assignment> + '_ {
+        self.assignments
+            .iter()
+            .filter(move |assignment| assignment.target.contains(cell))
+    }
+}
+
+/// Build the semantic model of a parsed file.
+///
+/// Statements that failed to parse are skipped, so this is only meaningful if
+/// `parse.errors` is empty.
+pub fn analyze(parse: &Parse) -> Sheet {
+    replay(parse, usize::MAX)
+}
+
+/// Like [`analyze`], but only apply the statements starting on lines `1..=line`.
+///
+/// This is the state of the sheet as it was just after `line` was written.
+pub fn replay(parse: &Parse, line: usize) -> Sheet {
+    let mut cx = Analyzer {
+        parse,
+        sheet: Sheet::default(),
+        last_read: HashMap::new(),
+        used: Some(0),
+        choices: BTreeMap::new(),
+    };
+    let root = parse.red_tree();
+    let mut current = 1;
+    for elem in root.children_with_tokens() {
+        if current > line {
+            break;
+        }
+        current += match elem {
+            NodeOrToken::Node(node) => {
+                if node.kind() == STATEMENT {
+                    cx.statement(node);
+                }
+                node.descendants_with_tokens()
+                    .filter_map(|elem| elem.into_token())
+                    .map(|token| newlines(parse, token))
+                    .sum::<usize>()
+            }
+            NodeOrToken::Token(token) => newlines(parse, token),
+        };
+    }
+    cx.enum_member_case();
+    cx.sheet
+}
+
+fn newlines(parse: &Parse, token: &SyntaxToken) -> usize {
+    parse.text(token).matches('\n').count()
+}
+
+struct Analyzer<'p> {
+    parse: &'p Parse,
+    sheet: Sheet,
+    /// The index in `sheet.assignments` of the last assignment that read each cell.
+    last_read: HashMap<CellRef, usize>,
+    /// How many cells the assignments so far wrote and read, or `None` once an
+    /// assignment went over [`MAX_SHEET_CELLS`] and no more are applied.
+    used: Option<u128>,
+    /// The string assignments that pick an option of an enum, with the string
+    /// literal and the enum's range, keyed by their index in `sheet.assignments`.
+    choices: BTreeMap<usize, (TextRange, RangeRef)>,
+}
+
+impl Analyzer<'_> {
+    fn statement(&mut self, stmt: &SyntaxNode) {
+        let Some(inner) = stmt.first_child() else {
+            return;
+        };
+        match inner.kind() {
+            ASSIGN => self.assign(inner),
+            ALIAS_STMT => self.alias_stmt(inner),
+            _ => {}
+        }
+    }
+
+    fn alias_stmt(&mut self, node: &SyntaxNode) {
+        let Some(name) = tokens(node).find(|t| t.kind() == IDENT) else {
+            return;
+        };
+        let Some(target) = node.first_child().and_then(|place| self.place(place)) else {
+            return;
+        };
+        let alias = Alias {
+            name: self.parse.text(name).to_owned(),
+            target,
+            span: name.text_range(),
+        };
+        self.sheet
+            .aliases
+            .insert(alias.name.clone(), self.sheet.definitions.len());
+        self.sheet.definitions.push(alias);
+    }
+
+    fn assign(&mut self, node: &SyntaxNode) {
+        let mut children = node.children();
+        let (Some(place), Some(expr)) = (children.next(), children.next()) else {
+            return;
+        };
+        let (Some(target), Some(value)) = (self.place(place), self.expr(expr)) else {
+            return;
+        };
+        let span = trimmed_range(place);
+        let read = match value {
+            Value::Enum(range) | Value::Ref(range) => Some(range),
+            Value::Int(_) | Value::Str(_) => None,
+        };
+        if !self.use_cells(target, read, node) {
+            return;
+        }
+        let index = self.sheet.assignments.len();
+        let mut is_choice = false;
+        if let Value::Str(_) = value {
+            if let Some(range) = self.enum_of(target) {
+                let literal = tokens(expr).next().map_or(span, |t| t.text_range());
+                self.choices.insert(index, (literal, range));
+                is_choice = true;
+            }
+        }
+        for cell in read.into_iter().flat_map(RangeRef::cells) {
+            self.last_read.insert(cell, index);
+        }
+
+        // Previous assignments that lose some of their cells, and whether none of
+        // those was read since. Picking an option doesn't lose the enum, which
+        // still types the cell.
+        let mut lost: BTreeMap<usize, bool> = BTreeMap::new();
+        for cell in target.cells() {
+            if let Some(prev) = self.sheet.cells.insert(cell, index) {
+                let picked =
+                    is_choice && matches!(self.sheet.assignments[prev].value, Value::Enum(_));
+                let unread = self.last_read.get(&cell).map_or(true, |&last| last <= prev);
+                if !picked {
+                    *lost.entry(prev).or_insert(true) &= unread;
+                }
+            }
+        }
+        // Only those that lose all their cells had no effect.
+        let overwritten: Vec<_> = lost
+            .into_iter()
+            .filter(|&(prev, unread)| {
+                let previous = self.sheet.assignments[prev].target;
+                unread && target.contains(previous.start()) && target.contains(previous.end())
+            })
+            .map(|(prev, _)| prev)
+            .collect();
+        for prev in overwritten {
+            self.sheet.issues.push(Issue::Overwritten {
+                span,
+                previous: self.sheet.assignments[prev].span,
+            });
+        }
+        self.sheet.assignments.push(Assignment {
+            target,
+            value,
+            span,
+        });
+    }
+
+    /// Count the cells an assignment writes and reads against [`MAX_SHEET_CELLS`].
+    /// Returns whether the assignment fits; the first one that doesn't is reported.
+    fn use_cells(&mut self, target: RangeRef, read: Option<RangeRef>, node: &SyntaxNode) -> bool {
+        let Some(used) = self.used else {
+            return false;
+        };
+        // Both sizes are at most `MAX_RANGE_CELLS`, so this can't overflow.
+        let size = |range: RangeRef| range.size().unwrap_or(MAX_RANGE_CELLS);
+        let used = used + size(target) + read.map_or(0, size);
+        if used > MAX_SHEET_CELLS {
+            self.used = None;
+            self.sheet.issues.push(Issue::SheetTooLarge {
+                span: trimmed_range(node),
+            });
+            return false;
+        }
+        self.used = Some(used);
+        true
+    }
+
+    /// The enum that some cell of `target` currently holds, either directly or
+    /// through a string that picked one of its options.
+    fn enum_of(&self, target: RangeRef) -> Option<RangeRef> {
+        target.cells().find_map(|cell| {
+            let prev = *self.sheet.cells.get(&cell)?;
+            match self.sheet.assignments[prev].value {
+                Value::Enum(range) => Some(range),
+                _ => self.choices.get(&prev).map(|&(_, range)| range),
+            }
+        })
+    }
+
+    /// Report the enum choices that only match an option when ignoring case. This
+    /// evaluates the options, so it can only run once every statement was applied.
+    fn enum_member_case(&mut self) {
+        let mut issues = Vec::new();
+        let mut evaluator = Evaluator::new(&self.sheet);
+        for (&index, &(span, range)) in &self.choices {
+            let Value::Str(value) = &self.sheet.assignments[index].value else {
+                continue;
+            };
+            let options: Vec<_> = range
+                .cells()
+                .filter_map(|cell| match evaluator.eval(cell) {
+                    Ok(Some(Evaluated::Str(option))) => Some(option),
+                    _ => None,
+                })
+                .collect();
+            if options.contains(value) {
+                continue;
+            }
+            let lowercase = value.to_lowercase();
+            if let Some(option) = options
+                .into_iter()
+                .find(|option| option.to_lowercase() == lowercase)
+            {
+                issues.push(Issue::EnumMemberCase { span, option });
+            }
+        }
+        self.sheet.issues.extend(issues);
+    }
+
+    fn place(&mut self, place: &SyntaxNode) -> Option<RangeRef> {
+        let Some(inner) = place.first_child() else {
+            let cell = self.cell(tokens(place).find(|t| t.kind() == CELL)?)?;
+            return Some(cell.into());
+        };
+        match inner.kind() {
+            CELL_RANGE => {
+                let mut cells = tokens(inner).filter(|t| t.kind() == CELL);
+                let start = self.cell(cells.next()?)?;
+                let end = self.cell(cells.next()?)?;
+                let range = RangeRef::new(start, end);
+                if range
+                    .size()
+                    .filter(|&size| size <= MAX_RANGE_CELLS)
+                    .is_none()
+                {
+                    self.sheet.issues.push(Issue::RangeTooLarge {
+                        span: trimmed_range(inner),
+                    });
+                    return None;
+                }
+                Some(range)
+            }
+            ALIAS_EXPR => {
+                let token = tokens(inner).find(|t| t.kind() == IDENT)?;
+                let name = self.parse.text(token);
+                let definition = self.sheet.aliases.get(name).copied();
+                self.sheet.references.push(AliasRef {
+                    name: name.to_owned(),
+                    span: token.text_range(),
+                    definition,
+                });
+                match definition {
+                    Some(i) => Some(self.sheet.definitions[i].target),
+                    None => {
+                        self.sheet.issues.push(Issue::UndefinedAlias {
+                            name: name.to_owned(),
+                            span: trimmed_range(inner),
+                            similar: self.similar_alias(name),
+                        });
+                        None
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The defined alias closest to `name`, if any is close enough to be a typo.
+    fn similar_alias(&self, name: &str) -> Option<String> {
+        let max_distance = (name.chars().count() / 3).max(1);
+        self.sheet
+            .aliases
+            .keys()
+            .map(|alias| (edit_distance(name, alias), alias))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .min()
+            .map(|(_, alias)| alias.clone())
+    }
+
+    fn cell(&mut self, token: &SyntaxToken) -> Option<CellRef> {
+        let cell = self.parse.text(token).parse().ok();
+        if cell.is_none() {
+            self.sheet.issues.push(Issue::InvalidCell {
+                span: token.text_range(),
+            });
+        }
+        cell
+    }
+
+    fn expr(&mut self, expr: &SyntaxNode) -> Option<Value> {
+        if let Some(inner) = expr.first_child() {
+            return match inner.kind() {
+                ENUM_EXPR => self.place(inner.first_child()?).map(Value::Enum),
+                PLACE => self.place(inner).map(Value::Ref),
+                _ => None,
+            };
+        }
+        let token = tokens(expr).next()?;
+        let text = self.parse.text(token);
+        match token.kind() {
+            INT => match text.parse() {
+                Ok(n) => Some(Value::Int(n)),
+                Err(_) => {
+                    self.sheet.issues.push(Issue::IntOutOfRange {
+                        span: token.text_range(),
+                    });
+                    None
+                }
+            },
+            // note that we don't have any string escapes
+            STR => Some(Value::Str(text[1..text.len() - 1].to_owned())),
+            _ => None,
+        }
+    }
+}
+
+/// The Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The direct child tokens of `node`, skipping whitespace.
+fn tokens(node: &SyntaxNode) -> impl Iterator<Item = &SyntaxToken> {
+    node.children_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .filter(|t| t.kind() != WHITESPACE)
+}
+
+/// The range of `node` without its leading whitespace.
+pub(crate) fn trimmed_range(node: &SyntaxNode) -> TextRange {
+    let range = node.text_range();
+    let start = node
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .find(|t| t.kind() != WHITESPACE)
+        .map_or(range.start(), |t| t.text_range().start());
+    TextRange::new(start, range.end())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cell(name: &str) -> CellRef {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn last_write_wins() {
+        let parse = crate::parse("A1:A3 = 1\nA2 = 2\nB2 = 3\nB1:B3 = 4\n");
+        let sheet = analyze(&parse);
+        let value = |name| sheet.get(cell(name)).map(|a| a.value.clone());
+        assert_eq!(value("A1"), Some(Value::Int(1)));
+        assert_eq!(value("A2"), Some(Value::Int(2)));
+        assert_eq!(value("A3"), Some(Value::Int(1)));
+        assert_eq!(value("B2"), Some(Value::Int(4)));
+        // setting a default for a range isn't reported, but hiding all of `B2` is
+        let previous: Vec<_> = sheet
+            .issues
+            .iter()
+            .map(|issue| match issue {
+                Issue::Overwritten { previous, .. } => usize::from(previous.start()),
+                _ => panic!("unexpected {issue:?}"),
+            })
+            .collect();
+        assert_eq!(previous, [17]);
+    }
+
+    #[test]
+    fn cells_in() {
+        let parse = crate::parse("A1 = 1\nB1:B5 = 2\nB9 = 3\nC2 = 4\nE3 = 5\n");
+        let sheet = analyze(&parse);
+        let cells = |range: &str| {
+            let (start, end) = range.split_once(':').unwrap();
+            let range = RangeRef::new(cell(start), cell(end));
+            sheet
+                .cells_in(range)
+                .map(|(cell, _)| cell.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(cells("B2:D4"), ["B2", "B3", "B4", "C2"]);
+        assert_eq!(
+            cells("A1:E9"),
+            ["A1", "B1", "B2", "B3", "B4", "B5", "B9", "C2", "E3"]
+        );
+        assert!(cells("D1:D9").is_empty());
+    }
+
+    #[test]
+    fn read_between_writes() {
+        let parse = crate::parse("D1 = 0\nE1 = D1\nD1 = 3\n");
+        let sheet = analyze(&parse);
+        assert!(sheet.issues.is_empty());
+        assert_eq!(sheet.get(cell("D1")).unwrap().value, Value::Int(3));
+    }
+
+    #[test]
+    fn enum_choice() {
+        let parse = crate::parse(
+            "A1 = \"apple\"\nA2 = \"banana\"\nB1 = enum A1:A2\nB1 = \"apple\"\nB1 = \"banana\"\n",
+        );
+        let sheet = analyze(&parse);
+        // Only the second pick overwrites anything.
+        assert_eq!(
+            sheet.issues,
+            [Issue::Overwritten {
+                span: TextRange::new(56.into(), 58.into()),
+                previous: TextRange::new(43.into(), 45.into()),
+            }]
+        );
+        assert_eq!(
+            sheet.get(cell("B1")).unwrap().value,
+            Value::Str("banana".to_owned())
+        );
+    }
+
+    #[test]
+    fn journal() {
+        let parse = crate::parse("D1 = 0\n\nD1 = 3\n// comment\nD1 = 4\n");
+        let d1 = cell("D1");
+        let value_at = |line| replay(&parse, line).get(d1).map(|a| a.value.clone());
+        assert_eq!(value_at(0), None);
+        assert_eq!(value_at(2), Some(Value::Int(0)));
+        assert_eq!(value_at(4), Some(Value::Int(3)));
+        assert_eq!(value_at(5), Some(Value::Int(4)));
+
+        let sheet = analyze(&parse);
+        let history: Vec<_> = sheet
+            .history(d1)
+            .map(|a| (a.value.clone(), usize::from(a.span.start())))
+            .collect();
+        assert_eq!(
+            history,
+            [(Value::Int(0), 0), (Value::Int(3), 8), (Value::Int(4), 26)]
+        );
+    }
+
+    #[test]
+    fn range_too_large() {
+        let parse = crate::parse("A1:XFD1048576 = 0\nalias all = A1:XFD1048576\nA1:A3 = 1\n");
+        let sheet = analyze(&parse);
+        let spans: Vec<_> = sheet
+            .issues
+            .iter()
+            .map(|issue| match issue {
+                Issue::RangeTooLarge { span } => usize::from(span.start()),
+                _ => panic!("unexpected {issue:?}"),
+            })
+            .collect();
+        assert_eq!(spans, [0, 30]);
+        assert!(sheet.alias("all").is_none());
+        assert_eq!(sheet.cells.len(), 3);
+    }
+
+    #[test]
+    fn sheet_too_large() {
+        // One column, then enums that each read all of it.
+        let source = "A1:A1048576 = \"x\"\n".to_owned()
+            + &["B1", "B2", "B3"]
+                .map(|cell| format!("{cell} = enum A1:A1048576\n"))
+                .concat();
+        let parse = crate::parse(&source);
+        let sheet = analyze(&parse);
+        assert_eq!(
+            sheet.issues,
+            [Issue::SheetTooLarge {
+                span: TextRange::new(62.into(), 83.into()),
+            }]
+        );
+        assert_eq!(sheet.assignments.len(), 3);
+        assert!(sheet.get(cell("B3")).is_none());
+    }
+
+    #[test]
+    fn similar_alias() {
+        let parse = crate::parse("alias fruits = A1:A3\nB1 = $friuts\nB2 = $vegetables\n");
+        let sheet = analyze(&parse);
+        let similar: Vec<_> = sheet
+            .issues
+            .iter()
+            .map(|issue| match issue {
+                Issue::UndefinedAlias { similar, .. } => similar.as_deref(),
+                _ => panic!("unexpected {issue:?}"),
+            })
+            .collect();
+        assert_eq!(similar, [Some("fruits"), None]);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn enum_member_case() {
+        let parse = crate::parse(
+            "A1 = \"apple\"\nA2 = \"Banana\"\nB1 = enum A1:A2\nB1 = \"APPLE\"\n\
+             B2 = enum A1:A2\nB2 = \"Banana\"\nB3 = \"Apple\"\n",
+        );
+        let sheet = analyze(&parse);
+        let cases: Vec<_> = sheet
+            .issues
+            .iter()
+            .filter_map(|issue| match issue {
+                Issue::EnumMemberCase { span, option } => {
+                    Some((usize::from(span.start()), option.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cases, [(48, "apple")]);
+    }
+}
@@ -0,0 +1,118 @@
+//! Check that the tree-sitter grammar in `tree-sitter-ssl` stays in sync with
+//! `grammar.rs`, by parsing every fixture in `tests/raw_dumps` with both and
+//! comparing their nodes.
+//!
+//! This uses the parser checked in to `tree-sitter-ssl/src`, and the `tree-sitter`
+//! CLI pinned in `tree-sitter-ssl/package.json`, installed with `npm ci`. Without
+//! it, the test is skipped, unless `CI` is set. The test doesn't regenerate the
+//! parser; CI checks separately that `src/` matches `grammar.js`.
+
+use std::fs;
+use std::path::Path;
+
+use xshell::{cmd, Shell};
+
+/// The kinds of our syntax tree that have a rule of the same name, in snake case,
+/// in the tree-sitter grammar. Other tokens, like `=`, are anonymous there.
+const NAMED: &[&str] = &[
+    "statement",
+    "assign",
+    "alias_stmt",
+    "expr",
+    "enum_expr",
+    "place",
+    "cell_range",
+    "alias_expr",
+    "cell",
+    "deleted_ref",
+    "int",
+    "str",
+    "ident",
+    "comment",
+];
+
+/// The named nodes of a tree dumped by `lang`, in preorder.
+fn cst_kinds(parse: &lang::Parse) -> Vec<String> {
+    // one `KIND@start..end` per line, like in the fixtures' `.stdout`
+    format!("{parse:?}")
+        .lines()
+        .filter_map(|line| {
+            let (kind, _) = line.trim_start().split_once('@')?;
+            let kind = kind.to_lowercase();
+            NAMED.contains(&kind.as_str()).then_some(kind)
+        })
+        .collect()
+}
+
+/// The named nodes of a tree printed by `tree-sitter parse`, in preorder.
+fn tree_sitter_kinds(tree: &str) -> Vec<String> {
+    // like `(source_file [0, 0] - [2, 0] (statement [0, 0] - [0, 6] ...`
+    tree.split('(')
+        .skip(1)
+        .filter_map(|node| {
+            let kind = node
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .next()?;
+            (kind != "source_file").then(|| kind.to_owned())
+        })
+        .collect()
+}
+
+#[test]
+fn conformance() {
+    let sh = Shell::new().unwrap();
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let grammar = root.join("../tree-sitter-ssl");
+    sh.change_dir(&grammar);
+    let installed = cmd!(sh, "npx --no-install tree-sitter --version")
+        .quiet()
+        .ignore_stdout()
+        .ignore_stderr()
+        .run()
+        .is_ok();
+    if !installed {
+        assert!(
+            std::env::var_os("CI").is_none(),
+            "the `tree-sitter` CLI is needed to check the grammar"
+        );
+        eprintln!("skipping: `tree-sitter` is not installed, run `npm ci` in tree-sitter-ssl");
+        return;
+    }
+    assert!(
+        grammar.join("src/parser.c").exists(),
+        "run `npx tree-sitter generate` in tree-sitter-ssl and commit `src/`"
+    );
+
+    let mut checked = 0;
+    for entry in fs::read_dir(root.join("tests/raw_dumps")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("ssl".as_ref()) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        let parse = lang::parse(&source);
+        // exits with an error if the file doesn't parse
+        let tree = cmd!(sh, "npx --no-install tree-sitter parse {path}")
+            .quiet()
+            .ignore_status()
+            .read()
+            .unwrap();
+        if parse.errors.is_empty() {
+            assert_eq!(
+                tree_sitter_kinds(&tree),
+                cst_kinds(&parse),
+                "{} parses differently",
+                path.display()
+            );
+        } else {
+            // the two recover from errors differently, so only check that both fail
+            assert!(
+                tree.contains("(ERROR") || tree.contains("(MISSING"),
+                "{} has syntax errors, which tree-sitter doesn't find",
+                path.display()
+            );
+        }
+        checked += 1;
+    }
+    assert!(checked > 0, "no fixtures found");
+}
@@ -56,11 +56,13 @@ fn config() -> Config {
     comment_defaults.base().normalize_stdout = filters;
     comment_defaults.base().exit_status = Spanned::dummy(0).into();
     comment_defaults.base().require_annotations = Spanned::dummy(false).into();
+    let mut program = CommandBuilder::cmd(cargo_build("ssl"));
+    program.args.push("dump".into());
     let mut config = Config {
         host: Some("irrelevant".into()),
         target: None,
         root_dir: "tests/raw_dumps".into(),
-        program: CommandBuilder::cmd(cargo_build("raw_dump")),
+        program,
         output_conflict_handling: error_on_output_conflict,
         bless_command: Some("cargo test -- -- --bless".into()),
         out_dir: std::env::var_os("CARGO_TARGET_DIR")
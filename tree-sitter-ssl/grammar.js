@@ -0,0 +1,63 @@
+/**
+ * @file Tree-sitter grammar for the spreadsheet language.
+ *
+ * This follows `lang/src/grammar.rs`, and has a rule of the same name, in
+ * snake case, for each node and named token of its syntax tree.
+ * `lang/tests/tree_sitter.rs` checks that both parse the fixtures in
+ * `lang/tests/raw_dumps` to the same nodes.
+ */
+
+module.exports = grammar({
+  name: 'ssl',
+
+  // horizontal whitespace; newlines end statements
+  extras: _ => [/[^\S\r\n]/],
+
+  word: $ => $.ident,
+
+  rules: {
+    source_file: $ => seq(
+      repeat(seq(optional($._line), $._newline)),
+      optional($._line),
+    ),
+
+    _line: $ => choice($.comment, $.statement),
+
+    _newline: _ => /\r?\n/,
+
+    comment: _ => token(seq('//', /[^\n]*/)),
+
+    statement: $ => choice($.alias_stmt, $.assign),
+
+    // A1 = 3
+    assign: $ => seq($.place, '=', $.expr),
+
+    // alias foo = A1
+    alias_stmt: $ => seq('alias', $.ident, '=', $.place),
+
+    expr: $ => choice($.enum_expr, $.int, $.str, $.place),
+
+    enum_expr: $ => seq('enum', $.place),
+
+    place: $ => choice($.cell_range, $.alias_expr, $.cell, $.deleted_ref),
+
+    // A1:A3
+    cell_range: $ => seq($.cell, ':', $.cell),
+
+    // $foo
+    alias_expr: $ => seq('$', $.ident),
+
+    // AAA123, which is also a valid name where a name is expected
+    cell: _ => token(prec(1, /[a-zA-Z]+[0-9]+/)),
+
+    // in place of a reference to cells that were deleted
+    deleted_ref: _ => '#REF!',
+
+    int: _ => /[0-9]+/,
+
+    // note that we don't have any string escapes
+    str: _ => /"[^"]*"/,
+
+    ident: _ => /[\p{XID_Start}_]\p{XID_Continue}*/,
+  },
+});
@@ -0,0 +1,18 @@
+; The same classes as `lang::ide::highlight`, as far as syntax alone can tell.
+
+(comment) @comment
+((comment) @attribute
+  (#match? @attribute "^//@"))
+
+["alias" "enum"] @keyword
+
+(cell) @variable
+(cell_range) @variable
+(deleted_ref) @variable
+(alias_stmt (ident) @constant)
+(alias_expr) @constant
+
+(int) @number
+(str) @string
+
+["=" ":" "$"] @operator